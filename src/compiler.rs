@@ -0,0 +1,584 @@
+mod cli;
+mod codegen;
+mod environment;
+mod format;
+mod glob;
+mod macros;
+mod prelude;
+mod reachability;
+mod runtime;
+mod shell_literal;
+
+use crate::errors::{ShispError, ShispWarning};
+use crate::lexer;
+use crate::parser;
+use crate::parser::form::Form;
+use crate::source_map::SourceMap;
+use crate::target::{Feature, Target};
+use self::codegen::Codegen;
+
+/// Settings that change the code the compiler generates.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    /// The shell the script is generated for. Features beyond POSIX sh are only used when this
+    /// shell has them.
+    pub target: Target,
+    /// Leave the comments of the source out of the script.
+    pub strip_comments: bool,
+    /// Start the script with `set -eu`, so that it stops at the first failing command or unset
+    /// variable.
+    pub strict: bool,
+    /// Keep the functions of the program and of the runtime that it never uses, as a library
+    /// sourced by other scripts needs.
+    pub keep_all: bool,
+}
+
+/// A compiled program, the warnings found while compiling it, the features beyond POSIX sh it
+/// requires, where each line of the script came from and the runtime functions left out of it.
+#[derive(Debug)]
+pub struct Output {
+    pub script: String,
+    pub warnings: Vec<ShispWarning>,
+    pub features: Vec<Feature>,
+    pub source_map: SourceMap,
+    pub removed: Vec<String>,
+}
+
+pub fn compile(input: String) -> Result<Output, ShispError> {
+    compile_with(input, &Options::default())
+}
+
+pub fn compile_with(input: String, options: &Options) -> Result<Output, ShispError> {
+    let ast = parser::parse_tokens(lexer::scan_string(input));
+    let (library, forms) = prelude::expand(Form::from_ast(&ast)?)?;
+    Codegen::new(options).compile_program(&library, &forms)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::ShispErrorType;
+    use std::process::Command;
+
+    fn run(input: &str) -> String {
+        run_on("sh", Target::Posix, input)
+    }
+
+    fn run_on(shell: &str, target: Target, input: &str) -> String {
+        run_with(shell, &Options { target, ..Options::default() }, input).1
+    }
+
+    /// Runs the compiled `input` with `shell`, returning its exit status and output.
+    fn run_with(shell: &str, options: &Options, input: &str) -> (i32, String) {
+        let script = compile_with(input.to_string(), options).unwrap().script;
+        let output = Command::new(shell).arg("-c").arg(&script).output().unwrap();
+        (output.status.code().unwrap(), String::from_utf8(output.stdout).unwrap())
+    }
+
+    #[test]
+    fn shell_literal_interpolation() {
+        let output = run(r#"
+            (define dir "my dir")
+            (define quote-me "it's $HOME")
+            (shell-literal "printf '%s|' ,dir \"in ,dir\" 'in ,quote-me'")
+            (let ((dir "inner"))
+              (shell-literal "printf '%s|' \"$(printf %s ,{dir})\""))
+        "#);
+        assert_eq!(output, "my dir|in my dir|in it's $HOME|inner|");
+    }
+
+    #[test]
+    fn shell_literal_unbound() {
+        let error = compile("(define x 1)\n(shell-literal \"echo ,y\")".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::UnboundVariable("y".to_string()));
+        assert_eq!(error.position(), ((1, 1), (0, 0)));
+    }
+
+    const SHOW: &str = r#"
+        (defun show (n more)
+          (let ((here n))
+            (cond (more (show more "")))
+            (shell-literal "printf '%s ' ,here")))
+        (show "outer" "inner")
+    "#;
+
+    #[test]
+    fn recursion_keeps_locals() {
+        assert_eq!(run(SHOW), "inner outer ");
+    }
+
+    #[test]
+    fn pipelines_and_redirections() {
+        let file = std::env::temp_dir().join(format!("shisp-pipes-{}", std::process::id()));
+        let output = run(&format!(r#"
+            (define file "{}")
+            (> file (pipe (printf "%s\n" "a b" "c") (tr "a-z" "A-Z")))
+            (>> file (pipe (printf "d\n")))
+            (define found (< file (pipe (grep "-ci" "[a-d]"))))
+            (cond ((2> "/dev/null" (pipe (ls "/nonexistent/shisp"))) (shell-literal "echo listed"))
+                  ((< file (pipe (grep "-q" "A B"))) (shell-literal "echo matched")))
+            (shell-literal "echo ,found")
+        "#, file.display()));
+        std::fs::remove_file(&file).unwrap();
+        assert_eq!(output, "3\nmatched\n#t\n");
+    }
+
+    #[test]
+    fn running_programs() {
+        let output = run(r#"
+            (define out (capture "printf" "%s\n\n" "x y"))
+            (shell-literal "echo \"[,out]\"")
+            (cond ((run "test" "-n" out) (shell-literal "echo ran")))
+            (define code (status "sh" "-c" "exit 3"))
+            (define ok (status (run "true")))
+            (define lines (capture (pipe (printf "a\nb\n") (grep "-c" ""))))
+            (shell-literal "echo ,code ,ok ,lines")
+        "#);
+        assert_eq!(output, "[x y]\nran\n3 0 2\n");
+    }
+
+    #[test]
+    fn loops() {
+        let output = run(r#"
+            (for-each (x '(a "b c" 3)) (shell-literal "printf '[%s]' \",x\""))
+            (for-each (x (list (car '(d e)) (cdr '(f)))) (shell-literal "printf '<%s>' \",x\""))
+            (dotimes (i 3) (shell-literal "printf %s ,i"))
+            (define n "")
+            (while (run "test" n "!=" "..") (set! n (capture "printf" "%s." n)))
+            (shell-literal "echo \" ,n\"")
+            (for-each-line (printf "one\ntwo words\nlast") (line) (shell-literal "echo \"[,line]\""))
+        "#);
+        assert_eq!(output, "[a][b c][3]<d><>012 ..\n[one]\n[two words]\n[last]\n");
+    }
+
+    #[test]
+    fn tail_calls() {
+        let output = run(r#"
+            (defun fib (a b n)
+              (cond ((run "test" n "-eq" 0) a)
+                    (else (fib b (capture "expr" a "+" b) (capture "expr" n "-" 1)))))
+            (define f (fib 0 1 20))
+            (shell-literal "echo ,f")
+        "#);
+        assert_eq!(output, "6765\n");
+    }
+
+    #[test]
+    fn closures() {
+        let output = run(r#"
+            (defun map (f l) (cond (l (cons (f (car l)) (map f (cdr l)))) (else '())))
+            (defun compose (f g) (lambda (x) (f (g x))))
+            (defun wrapper (pre post) (lambda (x) (capture "printf" "%s%s%s" pre x post)))
+            (define h (compose (wrapper "[" "]") (wrapper "(" ")")))
+            (for-each (w (map h '(a b))) (shell-literal "printf %s ,w"))
+            (for-each (c (map car '((x y) (z)))) (shell-literal "printf %s ,c"))
+            (define r (funcall (lambda (a b) (capture "expr" a "+" b)) 2 3))
+            (shell-literal "echo ,r")
+        "#);
+        assert_eq!(output, "[(a)][(b)]xz5\n");
+
+        // A parameter holding a function hides the global function of the same name.
+        let output = run(r#"
+            (defun f (x) "global")
+            (defun apply-to (f x) (f x))
+            (println (apply-to (lambda (x) (capture "printf" "<%s>" x)) "a") (f 1))
+        "#);
+        assert_eq!(output, "<a> global\n");
+
+        // Even the function's own name, when a call to it is in tail position.
+        let output = run(r#"
+            (defun f (f n) (f (capture "expr" n "+" 1)))
+            (println (f (lambda (x) (capture "printf" "<%s>" x)) 1))
+        "#);
+        assert_eq!(output, "<2>\n");
+    }
+
+    #[test]
+    fn environment_variables() {
+        let output = run(r#"
+            (define unset (getenv "SHISP_TEST_UNSET" (capture "printf" "default")))
+            (setenv! "SHISP_TEST_GREETING" "hi there")
+            (define mode "fast")
+            (export! SHISP_TEST_MODE mode)
+            (run "sh" "-c" "echo \"$SHISP_TEST_GREETING $SHISP_TEST_MODE\"")
+            (define greeting (getenv SHISP_TEST_GREETING "unused"))
+            (shell-literal "echo ,unset ,greeting")
+        "#);
+        assert_eq!(output, "hi there fast\ndefault hi there\n");
+    }
+
+    #[test]
+    fn command_line_parsing() {
+        let script = compile(r#"
+            (defcli "Copies files."
+              (flag verbose "Print more" "-v" "--verbose")
+              (option output "out.txt" "Where to write" "-o" "--output")
+              (arg input "The input file")
+              (rest files "More files"))
+            (shell-literal "echo \"v=,verbose o=,output i=,input\"")
+            (for-each (f files) (shell-literal "echo \"f=,f\""))
+        "#.to_string()).unwrap().script;
+        let run_with = |args: &[&str]| {
+            let output = Command::new("sh").arg("-c").arg(&script).arg("cp.sh").args(args).output().unwrap();
+            let text = String::from_utf8(output.stdout).unwrap() + &String::from_utf8(output.stderr).unwrap();
+            (output.status.code().unwrap(), text)
+        };
+
+        assert_eq!(run_with(&["-v", "in", "a", "b c"]), (0, "v=#t o=out.txt i=in\nf=a\nf=b c\n".to_string()));
+        assert_eq!(run_with(&["--output=x", "--", "-in"]), (0, "v= o=x i=-in\n".to_string()));
+        assert_eq!(run_with(&["-o", "y", "in"]), (0, "v= o=y i=in\n".to_string()));
+        assert_eq!(run_with(&["-o"]), (2, "cp.sh: -o expects a value\n".to_string()));
+        assert_eq!(run_with(&["-x"]), (2, "cp.sh: unknown option -x (see --help)\n".to_string()));
+        assert_eq!(run_with(&[]), (2, "cp.sh: missing <input> (see --help)\n".to_string()));
+        let (status, help) = run_with(&["--help"]);
+        assert_eq!(status, 0);
+        assert!(help.starts_with("usage: cp.sh [options] <input> [<files>...]\n\nCopies files.\n"));
+
+        let script = compile("(defcli (arg a \"A\"))".to_string()).unwrap().script;
+        let output = Command::new("sh").arg("-c").arg(&script).arg("s").args(["1", "2"]).output().unwrap();
+        assert_eq!(String::from_utf8(output.stderr).unwrap(), "s: unexpected argument 2 (see --help)\n");
+
+        let error = compile("(defun f () (defcli (flag v \"V\" \"-v\")))".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+    }
+
+    #[test]
+    fn error_handling() {
+        let input = r#"
+            (unwind-protect
+              (unwind-protect (run "echo" "body") (run "echo" "inner cleanup"))
+              (run "echo" "outer cleanup"))
+            (define r (try (run "echo" "in try") (run "false") (run "echo" "not reached")
+                        (catch status (shell-literal "echo caught ,status") "handled")))
+            (define ok (try (run "true")))
+            (define failed (try (unwind-protect (run "sh" "-c" "exit 3") (run "echo" "cleanup in try"))))
+            (shell-literal "echo \"r=,r ok=,ok failed=,failed\"")
+            (unwind-protect (run "false") (run "echo" "cleanup at exit"))
+            (run "echo" "after false")
+        "#;
+        let common = [
+            "body\ninner cleanup\nouter cleanup\n",
+            "in try\ncaught 1\n",
+            "cleanup in try\n",
+            "r=handled ok=#t failed=\n",
+            "cleanup at exit\n",
+        ].concat();
+        assert_eq!(run_with("sh", &Options::default(), input), (0, common.clone() + "after false\n"));
+        let strict = Options { strict: true, ..Options::default() };
+        for shell in ["sh", "bash"] {
+            assert_eq!(run_with(shell, &strict, input), (1, common.clone()));
+        }
+
+        let (status, _) = run_with("sh", &strict, "(shell-literal \"echo $UNSET_IN_SHISP_TEST\")");
+        assert_ne!(status, 0);
+
+        // A `try` stops at a failure in the test of a loop or a `cond`, which sh runs without `set -e`.
+        let input = r#"
+            (define n 0)
+            (while (try (run "test" n "-lt" 2) (run "echo" "tried"))
+              (set! n (capture "expr" n "+" 1)))
+            (cond ((2> "/dev/null" (try (run "false") (run "echo" "BAD"))) (println "redirected"))
+                  (else (println "failed")))
+            (cond ((pipe (try (run "false") (run "echo" "BAD")) (run "cat")) (println "piped")))
+            (println n)
+        "#;
+        let output = "tried\ntried\nfailed\npiped\n2\n".to_string();
+        assert_eq!(run_with("sh", &Options::default(), input), (0, output.clone()));
+        for shell in ["sh", "bash"] {
+            assert_eq!(run_with(shell, &strict, input), (0, output.clone()), "{}", shell);
+        }
+
+        // A function whose value is that of a failing command fails with it under `set -e`.
+        let input = r#"
+            (defun deploy () (run "true") (run "sh" "-c" "exit 4"))
+            (defun same? (a b) (run "test" a "=" b))
+            (println (try (deploy) (catch s s)) (try (same? "a" "b") (catch s s)) (same? "a" "a"))
+            (deploy)
+            (println "after deploy")
+        "#;
+        assert_eq!(run_with("sh", &Options::default(), input), (0, "4 1 #t\nafter deploy\n".to_string()));
+        for (shell, target) in [("sh", Target::Posix), ("bash", Target::Bash)] {
+            let options = Options { target, ..strict.clone() };
+            assert_eq!(run_with(shell, &options, input), (4, "4 1 #t\n".to_string()), "{}", shell);
+        }
+
+        // Tested in a `cond` or `while`, such a function may fail, as in an sh condition.
+        let input = r#"
+            (defun exists? (f) (run "test" "-e" f))
+            (cond ((exists? "/nonexistent") (println "found")) (else (println "missing")))
+            (while (exists? "/nonexistent") (println "looped"))
+            (println (string-join (filter exists? '("/" "/nonexistent")) ","))
+        "#;
+        assert_eq!(run_with("sh", &Options::default(), input), (0, "missing\n/\n".to_string()));
+        for (shell, target) in [("sh", Target::Posix), ("bash", Target::Bash)] {
+            let options = Options { target, ..strict.clone() };
+            assert_eq!(run_with(shell, &options, input), (0, "missing\n/\n".to_string()), "{}", shell);
+        }
+    }
+
+    #[test]
+    fn background_jobs() {
+        let output = run(r#"
+            (define slow (spawn (run "sh" "-c" "sleep 0.2; exit 4")))
+            (define fast (spawn (pipe (printf "%s\n" "x") (run "cat"))))
+            (define statuses (list (wait slow) (wait fast)))
+            (for-each (s statuses) (shell-literal "printf '%s ' ,s"))
+            (define ok (parallel (run "true") (run "sleep" "0.1")))
+            (define bad (parallel (run "sh" "-c" "exit 3") (run "true")))
+            (define caught (try (parallel (run "sh" "-c" "exit 5") (run "echo" "ran")) (catch s s)))
+            (shell-literal "echo \"ok=,ok bad=,bad caught=,caught\"")
+        "#);
+        assert_eq!(output, "x\n4 0 ran\nok=#t bad= caught=5\n");
+
+        // A job runs its own cleanups when it ends, but not those of the code that spawned it.
+        let output = run(r#"
+            (unwind-protect
+              (wait (spawn (unwind-protect (run "echo" "job") (run "echo" "job cleanup"))))
+              (run "echo" "outer cleanup"))
+        "#);
+        assert_eq!(output, "job\njob cleanup\nouter cleanup\n");
+    }
+
+    #[test]
+    fn case_patterns() {
+        let output = run(r#"
+            (defun kind (f)
+              (case f
+                ("*.tar.gz" "tarball")
+                (("a b" "c|d") "literal")
+                ("[0-9]*" "number")
+                ("\\*" "star")
+                ("[[:upper:]][[:digit:]]" "cell")
+                (("x[ ]y" "[']" "[$]") "bracket")
+                (else "other")))
+            (for-each (f '("x.tar.gz" "a b" "c|d" "c" "42" "*" "*x" "a" "B7" "x y" "'" "$" "x-y"))
+              (let ((k (kind f))) (shell-literal "printf '%s ' ,k")))
+        "#);
+        assert_eq!(output, "tarball literal literal other number star other other cell bracket bracket bracket other ");
+    }
+
+    #[test]
+    fn pattern_matching() {
+        let output = run(r#"
+            (defun describe (v)
+              (match v
+                (() "empty")
+                ("hi" "greeting")
+                ('stop "stop")
+                ((a) a)
+                ((a "to" b . rest) (cond (rest "long") (else b)))
+                ((_ . rest) (describe rest))
+                (_ "other")))
+            (for-each (v (list '() "hi" "stop" '(x) '(p to q) '(p to q r) '(p q) '(p q r) "z"))
+              (let ((d (describe v))) (shell-literal "printf '[%s] ' ,d")))
+        "#);
+        assert_eq!(output, "[empty] [greeting] [stop] [x] [q] [long] [q] [r] [other] ");
+    }
+
+    #[test]
+    fn structs() {
+        let output = run(r#"
+            (defstruct host name port)
+            (defstruct point x y)
+            (defun local-host (port) (make-host "local host" port))
+            (defun bump (h) (let ((port (host-port h))) (set-host-port! h (capture (run "expr" port "+" 1)))))
+            (define hosts (list (local-host 80) (make-host "it's" 22)))
+            (for-each (h hosts)
+              (bump h)
+              (let ((name (host-name h)) (port (host-port h)))
+                (shell-literal "printf '%s:%s ' ,name ,port")))
+            (let ((h (car hosts)) (p (make-point 1 2)))
+              (let ((a (host? h)) (b (host? p)) (c (host? "web")) (d (point-x h)) (e (set-point-x! h 5)))
+                (shell-literal "printf '[%s] ' ,a ,b ,c ,d ,e")))
+        "#);
+        assert_eq!(output, "local host:81 it's:23 [#t] [] [] [] [] ");
+    }
+
+    #[test]
+    fn maps() {
+        let program = r#"
+            (defun make-config () (let ((m (make-map))) (map-put! m "" "empty") m))
+            (define config (make-config))
+            (map-put! config "a b" 1)
+            (map-put! config "x]*$y'\"" "odd")
+            (map-put! config "a b" 2)
+            (for-each (k (map-keys config))
+              (let ((v (map-get config k))) (shell-literal "printf '<%s>=<%s> ' ,k ,v")))
+            (let ((a (map-has? config "")) (b (map-has? config "c")) (c (map-get config "c")) (d (map-get "x" "a")))
+              (shell-literal "printf '[%s] ' ,a ,b ,c ,d"))
+        "#;
+        let output = "<>=<empty> <a b>=<2> <x]*$y'\">=<odd> [#t] [] [] [] ";
+        assert_eq!(run(program), output);
+        let mut entries =
+            run_on("bash", Target::Bash, program).split_inclusive("> ").map(String::from).collect::<Vec<String>>();
+        entries.sort();
+        let mut expected = output.split_inclusive("> ").map(String::from).collect::<Vec<String>>();
+        expected.sort();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn output_forms() {
+        let output = run(r#"
+            (define dir "-n my\\dir")
+            (define line (format "~a items in ~s (100%)~%" 3 dir))
+            (print line)
+            (println "-e" dir (format "~x~~" 255))
+            (eprintln "hidden")
+            (print (format "-~a" "x"))
+            (println)
+        "#);
+        assert_eq!(output, "3 items in \"-n my\\dir\" (100%)\n-e -n my\\dir ff~\n-x\n");
+    }
+
+    #[test]
+    fn heredocs() {
+        let output = run(r#"
+            (define name "web $1")
+            (heredoc "cat" "literal $HOME `date`
+EOF
+")
+            (heredoc-interpolate "cat" "name=,name cost=$5 a,,b")
+            (define found (heredoc "grep" "-q" "x" "x"))
+            (println found)
+        "#);
+        assert_eq!(output, "literal $HOME `date`\nEOF\nname=web $1 cost=$5 a,b\n#t\n");
+    }
+
+    #[test]
+    fn paths() {
+        let table = [
+            ("a/b/c.txt", "c.txt", "a/b", "txt"),
+            ("a/b/", "b", "a", ""),
+            ("/", "/", "/", ""),
+            ("a", "a", ".", ""),
+            ("", "", ".", ""),
+            ("/a", "a", "/", ""),
+            ("a//b//", "b", "a", ""),
+            ("///x///", "x", "/", ""),
+            (".bashrc", ".bashrc", ".", ""),
+            ("x.tar.gz", "x.tar.gz", ".", "gz"),
+            ("dir.d/file", "file", "dir.d", ""),
+            ("a b/c d.e", "c d.e", "a b", "e"),
+        ];
+        let paths = table.iter().map(|(path, ..)| format!("{:?}", path)).collect::<Vec<String>>().join(" ");
+        let output = run(&format!(r#"
+            (for-each (p (list {}))
+              (let ((b (basename p)) (d (dirname p)) (e (path-extension p)))
+                (shell-literal "printf '%s|%s|%s\\n' ,b ,d ,e")))
+        "#, paths));
+        for ((path, basename, dirname, extension), line) in table.iter().zip(output.lines()) {
+            assert_eq!(line, format!("{}|{}|{}", basename, dirname, extension), "{:?}", path);
+            if !path.is_empty() {
+                for (command, expected) in [("basename", basename), ("dirname", dirname)] {
+                    let output = Command::new(command).arg(path).output().unwrap();
+                    let stdout = String::from_utf8(output.stdout).unwrap();
+                    assert_eq!(stdout, format!("{}\n", expected), "{} {:?}", command, path);
+                }
+            }
+        }
+        assert_eq!(output.lines().count(), table.len());
+
+        let output = run(r#"
+            (println (path-join "a" "b/" "c") (path-join "a/" "/abs" "x") (path-join "" "rel"))
+            (cond ((directory? "src") (println "dir")) (else (println "no dir")))
+            (println (file? "Cargo.toml") (file? "src") (executable? "/bin/sh")
+                     (file-exists? "missing") (symlink? "src"))
+        "#);
+        assert_eq!(output, "a/b/c /abs/x rel\ndir\n#t  #t  \n");
+    }
+
+    #[test]
+    fn strings() {
+        let table = [
+            ("Hello World", 1, Some(5), "Hell", "rld", "o", "0", " "),
+            ("a*b*c", 2, None, "a*", "*", "*", "-", "*"),
+            ("path/to/file", 0, Some(4), "path/", "file", "/", "::", "/"),
+            ("abc", 1, Some(10), "", "abc", "", "x", ""),
+            ("", 0, None, "x", "", "a", "b", ","),
+            ("x$y $z", 3, Some(4), "x$", "$z", "$", "\\$", "$"),
+        ];
+        for shell in ["sh", "bash"] {
+            let target = if shell == "sh" { Target::Posix } else { Target::Bash };
+            for (string, start, end, prefix, suffix, from, to, separator) in table {
+                let substring = match end {
+                    Some(end) => format!("(substring s {} {})", start, end),
+                    None => format!("(substring s {})", start),
+                };
+                let output = run_on(shell, target, &format!(r#"
+                    (define s {:?})
+                    (print (format "~a|~a|~a|~a|~a|~a|~a|[~a]~%"
+                      (string-length s) {} (string-prefix? s {:?}) (string-suffix? s {:?})
+                      (string-replace s {:?} {:?}) (string-upcase s) (string-downcase s)
+                      (string-join (string-split s {:?}) "][")))
+                "#, string, substring, prefix, suffix, from, to, separator));
+                let chars = string.chars().collect::<Vec<char>>();
+                let substring = chars[start.min(chars.len())..end.unwrap_or(chars.len()).min(chars.len())]
+                    .iter()
+                    .collect::<String>();
+                let truth = |b: bool| if b { "#t" } else { "" };
+                let replaced = if from.is_empty() { string.to_string() } else { string.replace(from, to) };
+                let pieces = if separator.is_empty() {
+                    chars.iter().map(|c| c.to_string()).collect::<Vec<String>>()
+                } else {
+                    string.split(separator).map(str::to_string).collect()
+                };
+                let expected = format!(
+                    "{}|{}|{}|{}|{}|{}|{}|[{}]\n",
+                    chars.len(),
+                    substring,
+                    truth(string.starts_with(prefix)),
+                    truth(string.ends_with(suffix)),
+                    replaced,
+                    string.to_uppercase(),
+                    string.to_lowercase(),
+                    pieces.join("][")
+                );
+                assert_eq!(output, expected, "{} {:?}", shell, string);
+            }
+        }
+    }
+
+    #[test]
+    fn prelude() {
+        let output = run(r#"
+            (define words (map (lambda (s) (string-upcase s)) '(ab b ac)))
+            (when (string-prefix? (car words) "A") (println "first" (car words)))
+            (unless #f (println (string-join (filter (lambda (s) (string-suffix? s "C")) words) ",")))
+            (begin (println (fold (lambda (a b) (string-join (list a b) "+")) "0" '(1 2 3))))
+            (demac twice (x) `(begin ,x ,x))
+            (twice (print "again "))
+            (assert (string-prefix? "abc" "b") "abc should start with" "b")
+            (println "not reached")
+        "#);
+        assert_eq!(output, "first AB\nAC\n0+1+2+3\nagain again ");
+
+        // Only the functions used are emitted, and a definition of the program replaces the prelude's.
+        let script = compile("(when #t (println 1))".to_string()).unwrap().script;
+        assert!(!script.contains("s_map") && !script.contains("s_reverse"));
+        let output = compile("(define l (filter (lambda (x) x) '(a)))".to_string()).unwrap();
+        assert!(output.script.contains("s_filter()") && output.script.contains("s_reverse()"));
+        assert!(!output.script.contains("s_fold()"));
+        let line = output.script.lines().position(|l| l == "s_filter() {").unwrap();
+        assert_eq!(output.source_map.lookup(line + 1), None);
+        assert_eq!(run("(defun fold (a b c) \"mine\") (println (fold 1 2 3))"), "mine\n");
+        let output = run(r#"
+            (defun f (x) "wrong")
+            (defun acc (a b) "wrong")
+            (println (string-join (map (lambda (x) (string-upcase x)) '(a b)) ","))
+            (println (string-join (filter (lambda (x) (string-prefix? x "a")) '(ab b)) ","))
+            (println (fold (lambda (acc x) (string-join (list acc x) "")) "" '(a b)))
+        "#);
+        assert_eq!(output, "A,B\nab\nab\n");
+        let program = "(demac when (x) `(println \"when\" ,x)) (when 1) (println (filter (lambda (x) x) '(\"\")))";
+        assert_eq!(run(program), "when 1\n\n");
+    }
+
+    #[test]
+    fn targets_run_on_their_shells() {
+        assert_eq!(run_on("dash", Target::Dash, SHOW), "inner outer ");
+        assert_eq!(run_on("bash", Target::Bash, SHOW), "inner outer ");
+        assert_eq!(run_on("bash", Target::Bash, "(define s \"a\tb\") (shell-literal \"printf %s ,s\")"), "a\tb");
+    }
+}
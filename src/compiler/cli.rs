@@ -0,0 +1,226 @@
+//! The declarations of a `defcli` form and the `--help` text generated from them.
+
+use crate::errors::{ShispError, ShispErrorType};
+use crate::parser::form::{Form, FormType};
+
+#[derive(Debug, PartialEq)]
+pub enum Kind<'a> {
+    /// A switch without a value, true when given.
+    Flag,
+    /// A switch taking a value, with the form of its default.
+    Option(&'a Form),
+    /// A required positional argument.
+    Arg,
+    /// The list of the remaining arguments.
+    Rest,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Param<'a> {
+    pub kind: Kind<'a>,
+    pub name: &'a str,
+    pub help: String,
+    pub switches: Vec<String>,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Cli<'a> {
+    pub description: Option<String>,
+    pub params: Vec<Param<'a>>,
+}
+
+/// Reads the arguments of `(defcli "description" specs...)`, where each spec is one of
+/// `(flag name "help" switches...)`, `(option name default "help" switches...)`,
+/// `(arg name "help")` or `(rest name "help")`.
+pub fn parse<'a>(form: &Form, args: &'a [Form]) -> Result<Cli<'a>, ShispError> {
+    let (description, specs) = match args.split_first() {
+        Some((Form { form_type: FormType::Str(text), .. }, specs)) => (Some(text.clone()), specs),
+        _ => (None, args),
+    };
+
+    let mut params: Vec<Param> = vec![];
+    for spec in specs {
+        let param = parse_param(spec)?;
+        if params.iter().any(|p| p.kind == Kind::Rest) && matches!(param.kind, Kind::Arg | Kind::Rest) {
+            return Err(spec.error(ShispErrorType::MalformedForm(
+                "no argument can follow the `rest` of the arguments".to_string(),
+            )));
+        }
+        for switch in &param.switches {
+            if switch == "-h" || switch == "--help" || params.iter().any(|p| p.switches.contains(switch)) {
+                return Err(spec.error(ShispErrorType::MalformedForm(format!("`{}` is declared twice", switch))));
+            }
+        }
+        if params.iter().any(|p| p.name == param.name) {
+            return Err(spec.error(ShispErrorType::MalformedForm(format!("`{}` is declared twice", param.name))));
+        }
+        params.push(param);
+    }
+    if params.is_empty() && description.is_none() {
+        return Err(form.error(ShispErrorType::MalformedForm(
+            "defcli expects `(defcli \"description\" specs...)`".to_string(),
+        )));
+    }
+    Ok(Cli { description, params })
+}
+
+fn parse_param(spec: &Form) -> Result<Param<'_>, ShispError> {
+    let malformed = || spec.error(ShispErrorType::MalformedForm(
+        "defcli specs are `(flag name \"help\" switches...)`, `(option name default \"help\" switches...)`, \
+         `(arg name \"help\")` or `(rest name \"help\")`".to_string(),
+    ));
+    let forms = spec.as_list().ok_or_else(malformed)?;
+    let (kind, name, rest) = match forms {
+        [kind, name, default, rest @ ..] if kind.as_atom() == Some("option") => (Kind::Option(default), name, rest),
+        [kind, name, rest @ ..] => match kind.as_atom() {
+            Some("flag") => (Kind::Flag, name, rest),
+            Some("arg") => (Kind::Arg, name, rest),
+            Some("rest") => (Kind::Rest, name, rest),
+            _ => return Err(malformed()),
+        },
+        _ => return Err(malformed()),
+    };
+    let name = name.as_atom().ok_or_else(malformed)?;
+    let (help, switches) = match rest.split_first() {
+        Some((help, switches)) => (string(help).ok_or_else(malformed)?, switches),
+        None => return Err(malformed()),
+    };
+
+    let switches = switches
+        .iter()
+        .map(|s| string(s).filter(|s| s.len() > 1 && s.starts_with('-') && s != "--").ok_or_else(|| {
+            s.error(ShispErrorType::MalformedForm("switches are strings starting with `-`".to_string()))
+        }))
+        .collect::<Result<Vec<String>, ShispError>>()?;
+    match kind {
+        Kind::Flag | Kind::Option(_) if switches.is_empty() => Err(spec.error(ShispErrorType::MalformedForm(
+            format!("`{}` needs at least one switch", name),
+        ))),
+        Kind::Arg | Kind::Rest if !switches.is_empty() => Err(spec.error(ShispErrorType::MalformedForm(
+            format!("positional argument `{}` cannot have switches", name),
+        ))),
+        kind => Ok(Param { kind, name, help, switches }),
+    }
+}
+
+fn string(form: &Form) -> Option<String> {
+    match &form.form_type {
+        FormType::Str(s) => Some(s.clone()),
+        _ => None,
+    }
+}
+
+impl Cli<'_> {
+    /// The `--help` text, as a `printf` format taking the name of the program.
+    pub fn help(&self) -> String {
+        let mut usage = "usage: %s".to_string();
+        let mut options = vec![];
+        let mut arguments = vec![];
+        for param in &self.params {
+            let placeholder = format!("<{}>", param.name);
+            match &param.kind {
+                Kind::Flag => options.push((param.switches.join(", "), param.help.clone())),
+                Kind::Option(default) => {
+                    let help = match &default.form_type {
+                        FormType::Str(s) if !s.is_empty() => format!("{} (default: {})", param.help, s),
+                        FormType::Number(n) => format!("{} (default: {})", param.help, n),
+                        _ => param.help.clone(),
+                    };
+                    options.push((format!("{} {}", param.switches.join(", "), placeholder), help));
+                }
+                Kind::Arg => {
+                    usage.push_str(&format!(" {}", placeholder));
+                    arguments.push((placeholder, param.help.clone()));
+                }
+                Kind::Rest => {
+                    usage.push_str(&format!(" [{}...]", placeholder));
+                    arguments.push((placeholder, param.help.clone()));
+                }
+            }
+        }
+        options.push(("-h, --help".to_string(), "Show this help".to_string()));
+        if options.len() > 1 {
+            usage.insert_str("usage: %s".len(), " [options]");
+        }
+
+        let mut text = format!("{}\n", usage);
+        if let Some(description) = &self.description {
+            text.push_str(&format!("\n{}\n", escape(description)));
+        }
+        let width = options.iter().chain(&arguments).map(|(left, _)| left.len()).max().unwrap_or(0);
+        for (title, entries) in [("options", &options), ("arguments", &arguments)] {
+            if entries.is_empty() {
+                continue;
+            }
+            text.push_str(&format!("\n{}:\n", title));
+            for (left, help) in entries {
+                let line = format!("  {:width$}  {}", escape(left), escape(help), width = width);
+                text.push_str(line.trim_end());
+                text.push('\n');
+            }
+        }
+        text
+    }
+}
+
+/// Escapes `text` for use in a `printf` format.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('%', "%%")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser;
+
+    fn read(input: &str) -> Vec<Form> {
+        Form::from_ast(&parser::parse_tokens(lexer::scan_string(input.to_string()))).unwrap()
+    }
+
+    #[test]
+    fn help_text() {
+        let forms = read(r#"(defcli "Copies 100% of files."
+            (flag verbose "Print more" "-v" "--verbose")
+            (option output "out.txt" "Where to write" "-o" "--output")
+            (arg input "The input file")
+            (rest files "More files"))"#);
+        let args = &forms[0].as_list().unwrap()[1..];
+        let cli = parse(&forms[0], args).unwrap();
+        assert_eq!(cli.params.len(), 4);
+        assert_eq!(cli.params[1].kind, Kind::Option(&args[2].as_list().unwrap()[2]));
+        assert_eq!(cli.help(), [
+            "usage: %s [options] <input> [<files>...]",
+            "",
+            "Copies 100%% of files.",
+            "",
+            "options:",
+            "  -v, --verbose          Print more",
+            "  -o, --output <output>  Where to write (default: out.txt)",
+            "  -h, --help             Show this help",
+            "",
+            "arguments:",
+            "  <input>                The input file",
+            "  <files>                More files",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn malformed_specs() {
+        for input in [
+            "(defcli)",
+            "(defcli (flag v \"help\"))",
+            "(defcli (arg a \"help\" \"-a\"))",
+            "(defcli (rest a \"help\") (arg b \"help\"))",
+            "(defcli (flag a \"help\" \"-a\") (flag b \"help\" \"-a\"))",
+            "(defcli (flag a \"help\" \"-h\"))",
+            "(defcli (switch a \"help\" \"-a\"))",
+            "(defcli (flag a \"help\" \"a\"))",
+        ] {
+            let forms = read(input);
+            let args = &forms[0].as_list().unwrap()[1..];
+            assert!(parse(&forms[0], args).is_err(), "{}", input);
+        }
+    }
+}
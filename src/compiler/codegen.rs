@@ -0,0 +1,3113 @@
+use super::cli::{self, Kind};
+use super::environment::{Environment, Function};
+use super::format;
+use super::glob::Pattern;
+use super::reachability;
+use super::runtime::{self, ASSOCIATIVE_MAPS, MAPS, RUNTIME};
+use super::shell_literal;
+use super::{Options, Output};
+use crate::errors::{ShispError, ShispErrorType, ShispWarning, ShispWarningType};
+use crate::sh_ast::{printer, AndOrOp, CaseArm, Command, Redirect, RedirectOp, Word, WordPart};
+use crate::sh_parser;
+use crate::parser::form::{Form, FormType};
+use crate::source_map::SourceMap;
+use crate::target::{Feature, Target};
+use std::collections::BTreeSet;
+
+/// The forms `compile_list` handles itself rather than as calls.
+const SPECIAL_FORMS: [&str; 62] = [
+    "define", "set!", "let", "defun", "depun", "shell-literal", "cond", "case", "match", "pipe",
+    ">", ">>", "<", "2>", "2>>", "2>&1", "run", "capture", "status", "quote", "list",
+    "while", "dotimes", "for-each", "for-each-line", "lambda", "funcall", "getenv",
+    "setenv!", "export!", "defcli", "defstruct", "try", "unwind-protect", "spawn", "wait", "parallel",
+    "make-map", "map-get", "map-put!", "map-keys", "map-has?", "print", "println", "eprintln", "format",
+    "heredoc", "heredoc-interpolate", "file-exists?", "file?", "directory?", "symlink?", "readable?",
+    "writable?", "executable?", "path-join", "string-length", "substring", "string-prefix?",
+    "string-suffix?", "string-upcase", "string-downcase",
+];
+
+/// The map operations, with the runtime functions they call and their arities. They are special
+/// forms so that the map functions are only added to the runtime of programs using them.
+const MAP_FORMS: [(&str, &str, usize); 5] = [
+    ("make-map", "shisp_map_new", 0),
+    ("map-get", "shisp_map_get", 2),
+    ("map-put!", "shisp_map_put", 3),
+    ("map-keys", "shisp_map_keys", 1),
+    ("map-has?", "shisp_map_has", 2),
+];
+
+/// Functions of the runtime callable from Shisp, with their sh names and arities.
+const BUILTINS: [(&str, &str, usize); 9] = [
+    ("cons", "shisp_cons", 2),
+    ("car", "shisp_car", 1),
+    ("cdr", "shisp_cdr", 1),
+    ("basename", "shisp_basename", 1),
+    ("dirname", "shisp_dirname", 1),
+    ("path-extension", "shisp_path_extension", 1),
+    ("string-replace", "shisp_string_replace", 3),
+    ("string-split", "shisp_string_split", 2),
+    ("string-join", "shisp_string_join", 2),
+];
+
+/// The predicates on files, with the `test` operators they are compiled to.
+const FILE_TESTS: [(&str, &str); 7] = [
+    ("file-exists?", "-e"),
+    ("file?", "-f"),
+    ("directory?", "-d"),
+    ("symlink?", "-L"),
+    ("readable?", "-r"),
+    ("writable?", "-w"),
+    ("executable?", "-x"),
+];
+
+fn nil() -> Word {
+    Word::literal("")
+}
+
+fn truth() -> Word {
+    Word::literal("#t")
+}
+
+/// Lowers Shisp forms to a POSIX sh script.
+///
+/// Every expression is compiled to the commands computing it, which are added to the current
+/// block, and a single sh word holding its value. Functions return their value in `shisp_r`, which
+/// callers copy to a temporary right away.
+///
+/// Features beyond POSIX sh are only used when the target has them, and every one that is used is
+/// recorded for the report.
+pub struct Codegen {
+    env: Environment,
+    target: Target,
+    strip_comments: bool,
+    block: Vec<Command>,
+    position: Option<(usize, usize)>,
+    temps: usize,
+    locals: Option<Vec<String>>,
+    warnings: Vec<ShispWarning>,
+    features: BTreeSet<Feature>,
+    /// How the value of the form about to be compiled is used.
+    context: Context,
+    recursion: Option<Recursion>,
+    /// The temporary holding the exit status the function being compiled returns, once a command
+    /// gives it its value.
+    status: Option<String>,
+    /// Functions lifted out of the code, from `lambda`s and `unwind-protect` cleanups, which are
+    /// defined at the start of the script.
+    lifted: Vec<Command>,
+    lambda_count: usize,
+    cleanup_count: usize,
+    /// Whether `set -e` is in effect where the code being compiled runs.
+    errexit: bool,
+    /// Whether the program uses maps, whose functions are then added to the runtime.
+    maps: bool,
+    /// Whether the functions of the prelude are being compiled, which have no place in the source.
+    prelude: bool,
+    /// Whether to keep the functions the program never uses.
+    keep_all: bool,
+}
+
+/// How the value of a form is used.
+#[derive(Debug, Clone, Copy, Default)]
+struct Context {
+    /// The form is in tail position in the function being compiled.
+    tail: bool,
+    /// The value is not used, so a command run for the form may be left to fail, as `set -e` and
+    /// `try` expect.
+    discarded: bool,
+    /// The form is the test of a `cond` clause or a `while` loop, so a function it calls runs as in
+    /// an sh condition: `set -e` does not apply to it, and its exit status does not end the script.
+    tested: bool,
+}
+
+const STATEMENT: Context = Context {
+    tail: false,
+    discarded: true,
+    tested: false,
+};
+
+/// The function being compiled, whose calls to itself in tail position become a loop.
+struct Recursion {
+    name: String,
+    params: Vec<String>,
+    looped: bool,
+}
+
+impl Codegen {
+    pub fn new(options: &Options) -> Codegen {
+        let mut env = Environment::new();
+        for (name, sh_name, arity) in BUILTINS {
+            env.define_builtin(name, sh_name, arity);
+        }
+        Codegen {
+            env,
+            target: options.target,
+            strip_comments: options.strip_comments,
+            block: vec![],
+            position: None,
+            temps: 0,
+            locals: None,
+            warnings: vec![],
+            features: BTreeSet::new(),
+            context: Context::default(),
+            recursion: None,
+            status: None,
+            lifted: vec![],
+            lambda_count: 0,
+            cleanup_count: 0,
+            errexit: options.strict,
+            maps: false,
+            prelude: false,
+            keep_all: options.keep_all,
+        }
+    }
+
+    /// Compiles a program, after the functions of the prelude it uses.
+    pub fn compile_program(mut self, library: &[Form], forms: &[Form]) -> Result<Output, ShispError> {
+        self.declare(library)?;
+        self.declare(forms)?;
+        let unused = match self.keep_all {
+            true => BTreeSet::new(),
+            false => reachability::unused(library.iter().chain(forms)),
+        };
+        if self.errexit {
+            self.emit(Command::call("set", vec![Word::literal("-eu")]));
+        }
+        let start = self.block.len();
+        self.prelude = true;
+        self.compile_top_level(library, &unused)?;
+        self.prelude = false;
+        self.compile_top_level(forms, &unused)?;
+        let lifted = std::mem::take(&mut self.lifted);
+        self.block.splice(start..start, lifted);
+
+        let mut runtime = RUNTIME.to_string();
+        if self.maps {
+            runtime.push_str(if self.uses(Feature::AssociativeArrays) { ASSOCIATIVE_MAPS } else { MAPS });
+        }
+        let mut removed = vec![];
+        if !self.keep_all {
+            (runtime, removed) = runtime::used(&runtime, &printer::print(&self.block));
+        }
+        self.block.insert(start, Command::Raw(runtime));
+
+        let (body, positions) = printer::print_mapped(&self.block);
+        let mut lines = vec![None];
+        lines.extend(positions);
+        Ok(Output {
+            script: format!("{}\n{}", self.target.shebang(), body),
+            warnings: self.warnings,
+            features: self.features.into_iter().collect(),
+            source_map: SourceMap::new(lines),
+            removed,
+        })
+    }
+
+    /// Compiles the top-level `forms`. The functions in `unused` are compiled to check them, but
+    /// left out of the script along with the lambdas they hold.
+    fn compile_top_level(&mut self, forms: &[Form], unused: &BTreeSet<&str>) -> Result<(), ShispError> {
+        for form in forms {
+            let name = match form.head() {
+                Some("defun" | "depun") => function_parts(form)?.0,
+                _ => "",
+            };
+            if !unused.contains(name) {
+                self.compile_statements(std::slice::from_ref(form))?;
+                continue;
+            }
+            let (lifted, features, maps) = (self.lifted.len(), self.features.clone(), self.maps);
+            self.collect(|cg| cg.compile_statements(std::slice::from_ref(form)))?;
+            self.lifted.truncate(lifted);
+            (self.features, self.maps) = (features, maps);
+            if !self.prelude {
+                self.warnings.push(ShispWarning::at(
+                    ShispWarningType::UnusedFunction(name.to_string()),
+                    form.row,
+                    form.col,
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Registers the top-level functions and variables so they can be used before their definition.
+    fn declare(&mut self, forms: &[Form]) -> Result<(), ShispError> {
+        for form in forms {
+            match form.head() {
+                Some(head @ ("defun" | "depun")) => {
+                    let (name, params, _) = function_parts(form)?;
+                    self.env.define_function(name, params.len(), head == "depun");
+                }
+                Some("define") => {
+                    let (name, _) = define_parts(form)?;
+                    self.env.bind_global(name);
+                }
+                Some("defstruct") => {
+                    for (name, arity, _) in struct_functions(form)? {
+                        self.env.define_function(&name, arity, !name.ends_with('!'));
+                    }
+                }
+                Some("defcli") => {
+                    for param in cli::parse(form, &form.as_list().unwrap()[1..])?.params {
+                        self.env.bind_global(param.name);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `feature` may be used, recording that it was if so.
+    fn uses(&mut self, feature: Feature) -> bool {
+        let supported = self.target.supports(feature);
+        if supported {
+            self.features.insert(feature);
+        }
+        supported
+    }
+
+    /// Adds `command` to the current block, marked with the position of the form being compiled.
+    fn emit(&mut self, command: Command) {
+        let command = match self.position {
+            Some(position) if !matches!(command, Command::Sourced { .. }) => Command::Sourced {
+                position,
+                command: Box::new(command),
+            },
+            _ => command,
+        };
+        self.block.push(command);
+    }
+
+    /// Runs `compile` with an empty block and returns the commands it emitted along with its result.
+    fn collect<T>(
+        &mut self,
+        compile: impl FnOnce(&mut Codegen) -> Result<T, ShispError>,
+    ) -> Result<(Vec<Command>, T), ShispError> {
+        let outer = std::mem::take(&mut self.block);
+        let result = compile(self);
+        let block = std::mem::replace(&mut self.block, outer);
+        Ok((block, result?))
+    }
+
+    fn temp(&mut self) -> String {
+        self.temps += 1;
+        let name = format!("shisp_t{}", self.temps);
+        if let Some(locals) = &mut self.locals {
+            locals.push(name.clone());
+        }
+        name
+    }
+
+    fn bind(&mut self, name: &str) -> String {
+        let sh_name = self.env.bind(name);
+        if let Some(locals) = &mut self.locals {
+            locals.push(sh_name.clone());
+        }
+        sh_name
+    }
+
+    fn compile_body(&mut self, forms: &[Form]) -> Result<Word, ShispError> {
+        self.compile_forms(forms, Context::default())
+    }
+
+    /// Compiles `forms` for their effects only.
+    fn compile_statements(&mut self, forms: &[Form]) -> Result<(), ShispError> {
+        self.compile_forms(forms, STATEMENT)?;
+        Ok(())
+    }
+
+    /// Compiles `forms` in order, returning the value of the last one, which is used as `context`
+    /// says. The values of the others are discarded.
+    fn compile_forms(&mut self, forms: &[Form], context: Context) -> Result<Word, ShispError> {
+        let mut word = nil();
+        for (i, form) in forms.iter().enumerate() {
+            let context = if i == forms.len() - 1 { context } else { STATEMENT };
+            word = self.compile_in(form, context)?;
+        }
+        Ok(word)
+    }
+
+    fn compile_in(&mut self, form: &Form, context: Context) -> Result<Word, ShispError> {
+        self.context = context;
+        self.compile_expr(form)
+    }
+
+    /// Whether the current block ends by jumping back to the start of the function.
+    fn ends_in_tail_call(&self) -> bool {
+        matches!(self.block.last().map(Command::unwrap_sourced), Some(Command::Simple { words, .. })
+            if words == &[Word::raw("continue")])
+    }
+
+    fn compile_expr(&mut self, form: &Form) -> Result<Word, ShispError> {
+        let context = std::mem::take(&mut self.context);
+        if !form.comments.is_empty() && !self.strip_comments {
+            self.emit(Command::Comment(form.comments.join("\n")));
+        }
+        match &form.form_type {
+            FormType::Str(s) => Ok(self.string(s)),
+            FormType::Number(n) => Ok(Word::literal(&n.to_string())),
+            FormType::Boolean(true) => Ok(truth()),
+            FormType::Boolean(false) => Ok(nil()),
+            FormType::Atom(name) => match (self.env.lookup(name), self.env.lookup_function(name)) {
+                (Some(var), _) => Ok(Word::var(var)),
+                (None, Some(function)) => Ok(Word::literal(&function.name)),
+                (None, None) => Err(form.error(ShispErrorType::UnboundVariable(name.clone()))),
+            },
+            FormType::List(forms) => {
+                let outer = match self.prelude {
+                    true => self.position,
+                    false => self.position.replace((form.row.0, form.col.0)),
+                };
+                let word = self.compile_list(form, forms, context);
+                self.position = outer;
+                word
+            }
+            FormType::Quote(quoted) => self.compile_quote(quoted),
+            FormType::Quasiquote(_) => Err(form.error(ShispErrorType::MalformedForm(
+                "quasiquotation is not supported yet".to_string(),
+            ))),
+            FormType::Unquote(_) | FormType::UnquoteSplice(_) => Err(form.error(ShispErrorType::MalformedForm(
+                "unquote outside of a quasiquote".to_string(),
+            ))),
+        }
+    }
+
+    /// A string constant. Control characters are written as escapes when the target has `$'...'`.
+    fn string(&mut self, s: &str) -> Word {
+        if s.chars().any(|c| c.is_ascii_control()) && self.uses(Feature::DollarSingleQuotes) {
+            Word(vec![WordPart::DollarQuoted(s.to_string())])
+        } else {
+            Word::literal(s)
+        }
+    }
+
+    /// The test for a true Shisp value.
+    fn test_true(&mut self, word: Word) -> Command {
+        if self.uses(Feature::DoubleBracketTests) {
+            Command::simple(vec![Word::raw("[["), Word::literal("-n"), word, Word::raw("]]")])
+        } else {
+            Command::test_true(word)
+        }
+    }
+
+    fn compile_list(&mut self, form: &Form, forms: &[Form], context: Context) -> Result<Word, ShispError> {
+        let head = match forms.first() {
+            Some(head) => head,
+            None => return Ok(nil()),
+        };
+
+        match head.as_atom() {
+            Some("define") => self.compile_define(form),
+            Some("set!") => self.compile_set(form, &forms[1..]),
+            Some("let") => self.compile_let(form, &forms[1..], context),
+            Some("defun") => self.compile_function(form),
+            Some("depun") => self.compile_function(form),
+            Some("shell-literal") => self.compile_shell_literal(form, &forms[1..]),
+            Some("cond") => self.compile_cond(&forms[1..], context),
+            Some("case") => self.compile_case(form, &forms[1..], context),
+            Some("match") => self.compile_match(form, &forms[1..], context),
+            Some("pipe") => self.compile_pipe(form, &forms[1..], context),
+            Some("run") => self.compile_run(form, &forms[1..], context),
+            Some("capture") => self.compile_capture(form, &forms[1..]),
+            Some("status") => self.compile_status(form, &forms[1..]),
+            Some("quote") => match &forms[1..] {
+                [quoted] => self.compile_quote(quoted),
+                args => Err(form.error(ShispErrorType::ArityMismatch("quote".to_string(), 1, args.len()))),
+            },
+            Some(name) if MAP_FORMS.iter().any(|(map_form, _, _)| *map_form == name) => {
+                self.compile_map_form(form, name, &forms[1..])
+            }
+            Some(name @ ("print" | "println" | "eprintln")) => self.compile_print(name, &forms[1..]),
+            Some("format") => self.compile_format(form, &forms[1..]),
+            Some(name @ ("heredoc" | "heredoc-interpolate")) => {
+                self.compile_heredoc(form, &forms[1..], name == "heredoc-interpolate", context)
+            }
+            Some(name) if FILE_TESTS.iter().any(|(test, _)| *test == name) => {
+                let test = self.file_test(form, name, &forms[1..])?;
+                Ok(self.succeeds(test))
+            }
+            Some("path-join") => {
+                let words = forms[1..]
+                    .iter()
+                    .map(|f| self.compile_expr(f))
+                    .collect::<Result<Vec<Word>, ShispError>>()?;
+                self.emit(Command::call("shisp_path_join", words));
+                let result = self.temp();
+                self.emit(Command::assign(&result, Word::var("shisp_r")));
+                Ok(Word::var(&result))
+            }
+            Some("string-length") => match &forms[1..] {
+                [string] => {
+                    let word = self.compile_expr(string)?;
+                    let var = self.stored(word);
+                    let result = self.temp();
+                    self.emit(Command::assign(&result, Word(vec![WordPart::Param(format!("#{}", var))])));
+                    Ok(Word::var(&result))
+                }
+                args => Err(form.error(ShispErrorType::ArityMismatch("string-length".to_string(), 1, args.len()))),
+            },
+            Some("substring") => self.compile_substring(form, &forms[1..]),
+            Some(name @ ("string-prefix?" | "string-suffix?")) => self.compile_affix_test(form, name, &forms[1..]),
+            Some(name @ ("string-upcase" | "string-downcase")) => self.compile_case_change(form, name, &forms[1..]),
+            Some("list") => {
+                let words = forms[1..]
+                    .iter()
+                    .map(|f| self.compile_expr(f))
+                    .collect::<Result<Vec<Word>, ShispError>>()?;
+                Ok(self.make_list(words))
+            }
+            Some("while") => self.compile_while(form, &forms[1..]),
+            Some("dotimes") => self.compile_dotimes(form, &forms[1..]),
+            Some("for-each") => self.compile_for_each(form, &forms[1..]),
+            Some("for-each-line") => self.compile_for_each_line(form, &forms[1..]),
+            Some("lambda") => self.compile_lambda(form, &forms[1..]),
+            Some("getenv") => self.compile_getenv(form, &forms[1..]),
+            Some("defcli") => self.compile_defcli(form, &forms[1..]),
+            Some("defstruct") => self.compile_defstruct(form),
+            Some("try") => self.compile_try(form, &forms[1..]),
+            Some("unwind-protect") => self.compile_unwind_protect(form, &forms[1..], context),
+            Some("spawn") => match &forms[1..] {
+                [job] => self.compile_spawn(job),
+                args => Err(form.error(ShispErrorType::ArityMismatch("spawn".to_string(), 1, args.len()))),
+            },
+            Some("wait") => match &forms[1..] {
+                [job] => {
+                    let job = self.compile_expr(job)?;
+                    Ok(self.exit_status(Command::call("wait", vec![job])))
+                }
+                args => Err(form.error(ShispErrorType::ArityMismatch("wait".to_string(), 1, args.len()))),
+            },
+            Some("parallel") => self.compile_parallel(&forms[1..], context),
+            Some(op @ ("setenv!" | "export!")) => self.compile_setenv(form, op, &forms[1..]),
+            Some("funcall") => match forms[1..].split_first() {
+                Some((function, args)) => {
+                    let function = self.compile_expr(function)?;
+                    self.compile_funcall(function, args, context)
+                }
+                None => Err(form.error(ShispErrorType::MalformedForm(
+                    "funcall expects `(funcall function args...)`".to_string(),
+                ))),
+            },
+            Some(op @ (">" | ">>" | "<" | "2>" | "2>>" | "2>&1")) => {
+                self.compile_redirect(form, op, &forms[1..], context)
+            }
+            Some(name)
+                if context.tail
+                    && self.env.lookup(name).is_none()
+                    && self.recursion.as_ref().is_some_and(|r| r.name == name) =>
+            {
+                self.compile_tail_call(form, name, &forms[1..])
+            }
+            Some(name) => self.compile_call(form, name, &forms[1..], context),
+            None => Err(head.error(ShispErrorType::MalformedForm(
+                "expected a function name".to_string(),
+            ))),
+        }
+    }
+
+    /// The value of a quoted form: atoms become strings and lists become lists of their quoted
+    /// elements.
+    fn compile_quote(&mut self, form: &Form) -> Result<Word, ShispError> {
+        let (prefix, quoted) = match &form.form_type {
+            FormType::Atom(name) => return Ok(Word::literal(name)),
+            FormType::List(forms) => {
+                let words = forms
+                    .iter()
+                    .map(|f| self.compile_quote(f))
+                    .collect::<Result<Vec<Word>, ShispError>>()?;
+                return Ok(self.make_list(words));
+            }
+            FormType::Quote(quoted) => ("quote", quoted),
+            FormType::Quasiquote(quoted) => ("quasiquote", quoted),
+            FormType::Unquote(quoted) => ("unquote", quoted),
+            FormType::UnquoteSplice(quoted) => ("unquote-splice", quoted),
+            _ => return self.compile_expr(form),
+        };
+        let quoted = self.compile_quote(quoted)?;
+        Ok(self.make_list(vec![Word::literal(prefix), quoted]))
+    }
+
+    /// Conses up a list of `words`, from the last one to the first.
+    fn make_list(&mut self, words: Vec<Word>) -> Word {
+        self.make_list_onto(words, nil())
+    }
+
+    /// Conses `words` in front of `list`.
+    fn make_list_onto(&mut self, words: Vec<Word>, mut list: Word) -> Word {
+        for word in words.into_iter().rev() {
+            self.emit(Command::call("shisp_cons", vec![word, list]));
+            let cell = self.temp();
+            self.emit(Command::assign(&cell, Word::var("shisp_r")));
+            list = Word::var(&cell);
+        }
+        list
+    }
+
+    fn compile_define(&mut self, form: &Form) -> Result<Word, ShispError> {
+        let (name, value) = define_parts(form)?;
+        let word = self.compile_expr(value)?;
+        let var = if self.env.is_global() {
+            self.env.bind_global(name)
+        } else {
+            self.bind(name)
+        };
+
+        self.emit(Command::assign(&var, word));
+        Ok(Word::var(&var))
+    }
+
+    fn compile_set(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (name, value) = match args {
+            [name, value] => (name, value),
+            _ => return Err(form.error(ShispErrorType::ArityMismatch("set!".to_string(), 2, args.len()))),
+        };
+        let var = match name.as_atom() {
+            Some(n) => self.env
+                .lookup(n)
+                .cloned()
+                .ok_or_else(|| name.error(ShispErrorType::UnboundVariable(n.to_string())))?,
+            None => return Err(name.error(ShispErrorType::MalformedForm(
+                "set! expects a variable name".to_string(),
+            ))),
+        };
+
+        let word = self.compile_expr(value)?;
+        self.emit(Command::assign(&var, word));
+        Ok(Word::var(&var))
+    }
+
+    fn compile_let(&mut self, form: &Form, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let bindings = args
+            .first()
+            .and_then(|b| b.as_list())
+            .ok_or_else(|| form.error(ShispErrorType::MalformedForm(
+                "let expects a list of bindings".to_string(),
+            )))?;
+
+        let mut values = vec![];
+        for binding in bindings {
+            let (name, value) = match (binding.as_atom(), binding.as_list()) {
+                (Some(name), _) => (name, nil()),
+                (_, Some([name, value])) if name.as_atom().is_some() => {
+                    (name.as_atom().unwrap(), self.compile_expr(value)?)
+                }
+                _ => return Err(binding.error(ShispErrorType::MalformedForm(
+                    "let bindings must be `name` or `(name value)`".to_string(),
+                ))),
+            };
+            values.push((name, value));
+        }
+
+        self.env.push_frame();
+        for (name, value) in values {
+            let var = self.bind(name);
+            self.emit(Command::assign(&var, value));
+        }
+        let word = self.compile_forms(&args[1..], context);
+        self.env.pop_frame();
+        word
+    }
+
+    fn compile_function(&mut self, form: &Form) -> Result<Word, ShispError> {
+        let (name, params, body) = function_parts(form)?;
+        let function = match self.env.lookup_function(name) {
+            Some(function) => function.clone(),
+            None => self.env
+                .define_function(name, params.len(), form.head() == Some("depun"))
+                .clone(),
+        };
+
+        let outer_locals = self.locals.replace(vec![]);
+        let outer_recursion = self.recursion.take();
+        let outer_status = self.status.take();
+        self.env.push_frame();
+        let result = self.collect(|cg| cg.compile_function_body(name, &params, body));
+        self.env.pop_frame();
+        self.recursion = outer_recursion;
+        let status = std::mem::replace(&mut self.status, outer_status);
+        let locals = std::mem::replace(&mut self.locals, outer_locals).unwrap_or_default();
+        let (mut body, ()) = result?;
+
+        match status {
+            Some(status) => {
+                body.insert(0, Command::assign(&status, Word::literal("0")));
+                self.emit_function_returning(&function, &locals, body, &status);
+            }
+            None => self.emit_function(&function, &locals, body),
+        }
+        Ok(nil())
+    }
+
+    /// Binds the parameters and compiles the body. When the body calls the function itself in tail
+    /// position, it is wrapped in a loop that such calls restart with new parameter values.
+    fn compile_function_body(&mut self, name: &str, params: &[&str], body: &[Form]) -> Result<(), ShispError> {
+        let mut vars = vec![];
+        for (i, param) in params.iter().enumerate() {
+            let var = self.bind(param);
+            self.emit(Command::assign(&var, positional(i + 1)));
+            vars.push(var);
+        }
+        self.recursion = Some(Recursion {
+            name: name.to_string(),
+            params: vars,
+            looped: false,
+        });
+
+        let (mut commands, ()) = self.collect(|cg| {
+            let word = cg.compile_forms(body, Context { tail: true, discarded: false, tested: false })?;
+            if !cg.ends_in_tail_call() {
+                cg.emit(Command::assign("shisp_r", word));
+            }
+            Ok(())
+        })?;
+        if self.recursion.as_ref().is_some_and(|r| r.looped) {
+            commands.push(Command::simple(vec![Word::raw("break")]));
+            self.emit(Command::While {
+                until: false,
+                condition: vec![Command::simple(vec![Word::raw(":")])],
+                body: commands,
+            });
+        } else {
+            self.block.extend(commands);
+        }
+        Ok(())
+    }
+
+    /// A call of the function being compiled to itself in tail position: the parameters are set to
+    /// the new arguments and the function's loop restarts.
+    fn compile_tail_call(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let (_, words) = self.call_args(form, name, args)?;
+        let recursion = self.recursion.as_mut().unwrap();
+        recursion.looped = true;
+        let params = recursion.params.clone();
+
+        // Every argument is evaluated before any parameter changes, as for a real call.
+        let mut assignments = vec![];
+        for (param, word) in params.iter().zip(words) {
+            if word.as_var() == Some(param) {
+                continue;
+            }
+            let settled = match word.as_var() {
+                Some(var) => !params.iter().any(|p| p == var),
+                None => word.0.iter().all(|part| matches!(part, WordPart::Literal(_))),
+            };
+            if settled {
+                assignments.push((param, word));
+            } else {
+                let temp = self.temp();
+                self.emit(Command::assign(&temp, word));
+                assignments.push((param, Word::var(&temp)));
+            }
+        }
+        for (param, word) in assignments {
+            self.emit(Command::assign(param, word));
+        }
+        self.emit(Command::simple(vec![Word::raw("continue")]));
+        Ok(nil())
+    }
+
+    /// `(lambda (params...) body...)`, lifted to a function of its own. Its value is the name of
+    /// that function or, when it uses local variables of the code around it, a closure: a list of
+    /// the name and the values of those variables, which are copied when the closure is made.
+    fn compile_lambda(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (params, body) = match args.split_first() {
+            Some((params, body)) => (lambda_params(params)?, body),
+            None => return Err(form.error(ShispErrorType::MalformedForm(
+                "lambda expects `(lambda (params...) body...)`".to_string(),
+            ))),
+        };
+
+        let mut names = vec![];
+        for form in body {
+            referenced_names(form, &mut names);
+        }
+        let mut captured: Vec<(String, String)> = vec![];
+        for name in names {
+            if params.contains(&name.as_str()) || captured.iter().any(|(n, _)| *n == name) {
+                continue;
+            }
+            if let Some(var) = self.env.lookup_local(&name) {
+                captured.push((name, var.clone()));
+            }
+        }
+
+        self.lambda_count += 1;
+        let function = Function {
+            name: format!("shisp_lambda{}", self.lambda_count),
+            arity: params.len(),
+            pure: false,
+        };
+        let outer_locals = self.locals.replace(vec![]);
+        let outer_recursion = self.recursion.take();
+        self.env.push_frame();
+        let result = self.collect(|cg| {
+            if let Some((_, rest)) = captured.split_last() {
+                let env = cg.temp();
+                cg.emit(Command::assign(&env, positional(1)));
+                for (i, (name, _)) in captured.iter().enumerate() {
+                    cg.emit(Command::call("shisp_car", vec![Word::var(&env)]));
+                    let var = cg.bind(name);
+                    cg.emit(Command::assign(&var, Word::var("shisp_r")));
+                    if i < rest.len() {
+                        cg.emit(Command::call("shisp_cdr", vec![Word::var(&env)]));
+                        cg.emit(Command::assign(&env, Word::var("shisp_r")));
+                    }
+                }
+            }
+            let offset = usize::from(!captured.is_empty());
+            for (i, param) in params.iter().enumerate() {
+                let var = cg.bind(param);
+                cg.emit(Command::assign(&var, positional(i + 1 + offset)));
+            }
+            let word = cg.compile_body(body)?;
+            cg.emit(Command::assign("shisp_r", word));
+            Ok(())
+        });
+        self.env.pop_frame();
+        self.recursion = outer_recursion;
+        let locals = std::mem::replace(&mut self.locals, outer_locals).unwrap_or_default();
+        let (body, ()) = result?;
+        let (definition, ()) = self.collect(|cg| {
+            cg.emit_function(&function, &locals, body);
+            Ok(())
+        })?;
+        self.lifted.extend(definition);
+
+        if captured.is_empty() {
+            return Ok(Word::literal(&function.name));
+        }
+        let values = captured.iter().map(|(_, var)| Word::var(var)).collect();
+        let env = self.make_list(values);
+        Ok(self.make_list_onto(vec![Word::literal(&function.name)], env))
+    }
+
+    /// Calls the function value `function`, a function name or a closure, with `args`.
+    fn compile_funcall(&mut self, function: Word, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let mut words = vec![function];
+        for arg in args {
+            words.push(self.compile_expr(arg)?);
+        }
+        self.emit_call(Command::call("shisp_funcall", words), context);
+
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word::var("shisp_r")));
+        Ok(Word::var(&result))
+    }
+
+    fn emit_function(&mut self, function: &Function, locals: &[String], body: Vec<Command>) {
+        let commands = self.function_commands(locals, body);
+        self.emit(Command::FunctionDef {
+            name: function.name.clone(),
+            body: commands,
+        });
+    }
+
+    /// Defines a function that returns the exit status held by its local `status`, which is
+    /// copied to `shisp_status` before the locals are restored.
+    fn emit_function_returning(
+        &mut self,
+        function: &Function,
+        locals: &[String],
+        mut body: Vec<Command>,
+        status: &str,
+    ) {
+        let returned = match self.uses(Feature::Local) {
+            true => status,
+            false => {
+                body.push(Command::assign("shisp_status", Word::var(status)));
+                "shisp_status"
+            }
+        };
+        let mut commands = self.function_commands(locals, body);
+        commands.push(Command::simple(vec![Word::raw("return"), Word::var(returned)]));
+        self.emit(Command::FunctionDef {
+            name: function.name.clone(),
+            body: commands,
+        });
+    }
+
+    /// `body` with the commands giving `locals` their own value per call around it.
+    fn function_commands(&mut self, locals: &[String], body: Vec<Command>) -> Vec<Command> {
+        let locals = locals.iter().map(|l| Word::literal(l)).collect::<Vec<Word>>();
+        let mut commands = vec![];
+        if locals.is_empty() {
+            commands.extend(body);
+        } else if self.uses(Feature::Local) {
+            commands.push(Command::call("local", locals));
+            commands.extend(body);
+        } else {
+            commands.push(Command::call("shisp_save", locals.clone()));
+            commands.extend(body);
+            commands.push(Command::call("shisp_restore", locals));
+        }
+        commands
+    }
+
+    /// `(defstruct name fields...)`, which defines `make-name`, `name-field`, `set-name-field!`
+    /// and `name?` over the records of the runtime.
+    fn compile_defstruct(&mut self, form: &Form) -> Result<Word, ShispError> {
+        for (name, arity, body) in struct_functions(form)? {
+            let function = self.env.define_function(&name, arity, !name.ends_with('!')).clone();
+            self.emit_function(&function, &[], vec![body]);
+        }
+        Ok(nil())
+    }
+
+    fn compile_shell_literal(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let mut text = String::new();
+        let mut parts = vec![];
+        for arg in args {
+            if !parts.is_empty() {
+                text.push(' ');
+            }
+            parts.push((text.chars().count(), arg));
+            text.push_str(&match &arg.form_type {
+                FormType::Str(s) => s.clone(),
+                FormType::Atom(a) => a.clone(),
+                FormType::Number(n) => n.to_string(),
+                FormType::Unquote(inner) if inner.as_atom().is_some() => {
+                    format!(",{{{}}}", inner.as_atom().unwrap())
+                }
+                _ => return Err(arg.error(ShispErrorType::MalformedForm(
+                    "shell-literal only accepts strings, atoms and unquoted variables".to_string(),
+                ))),
+            });
+        }
+
+        match sh_parser::check(&text) {
+            Ok(bashisms) => {
+                for bashism in bashisms {
+                    self.features.insert(bashism.feature);
+                    if self.target.supports(bashism.feature) {
+                        continue;
+                    }
+                    let warning = match self.target {
+                        Target::Posix => ShispWarningType::Bashism(bashism.message),
+                        target => ShispWarningType::Unsupported(bashism.feature, target),
+                    };
+                    let (row, col) = literal_position(&parts, bashism.offset);
+                    self.warnings.push(ShispWarning::at(warning, row, col));
+                }
+            }
+            Err(error) => {
+                let (row, col) = literal_position(&parts, error.offset);
+                return Err(ShispError::at(ShispErrorType::ShellSyntax(error.message), row, col));
+            }
+        }
+
+        let env = &self.env;
+        let text = shell_literal::interpolate(&text, |name| env.lookup(name).cloned())
+            .map_err(|e| form.error(e))?;
+        self.emit(Command::Raw(text));
+        Ok(nil())
+    }
+
+    fn compile_cond(&mut self, clauses: &[Form], context: Context) -> Result<Word, ShispError> {
+        let result = self.temp();
+        for command in self.compile_clauses(clauses, &result, context)? {
+            self.emit(command);
+        }
+        Ok(Word::var(&result))
+    }
+
+    /// Compiles `clauses` to an `if` whose `else` holds the remaining clauses, storing the value of
+    /// the clause taken in `result`. The printer turns the nesting back into an `elif` chain
+    /// wherever a test needs no commands of its own.
+    fn compile_clauses(
+        &mut self,
+        clauses: &[Form],
+        result: &str,
+        context: Context,
+    ) -> Result<Vec<Command>, ShispError> {
+        let (clause, rest) = match clauses.split_first() {
+            Some(split) => split,
+            None => return Ok(vec![Command::assign(result, nil())]),
+        };
+        let (test, body) = match clause.as_list() {
+            Some([test, body @ ..]) => (test, body),
+            _ => return Err(clause.error(ShispErrorType::MalformedForm(
+                "cond clauses must be `(test body...)`".to_string(),
+            ))),
+        };
+
+        let clause_body = |cg: &mut Codegen| {
+            let word = cg.compile_forms(body, context)?;
+            if !cg.ends_in_tail_call() {
+                cg.emit(Command::assign(result, word));
+            }
+            Ok(())
+        };
+        if test.as_atom() == Some("else") || test.form_type == FormType::Boolean(true) {
+            return Ok(self.collect(clause_body)?.0);
+        }
+
+        let (mut commands, condition) = self.collect(|cg| cg.compile_test(test))?;
+        let (then_body, ()) = self.collect(clause_body)?;
+        let else_body = self.compile_clauses(rest, result, context)?;
+        commands.push(Command::If {
+            condition: vec![condition],
+            then_body,
+            else_body: Some(else_body),
+        });
+        Ok(commands)
+    }
+
+    /// `(case expr (patterns body...)...)`, compiled to an sh `case`. The patterns of a clause are a
+    /// glob pattern string or a list of them, or `else`, and the value is that of the body of the
+    /// first clause with a pattern matching `expr`, or nil.
+    fn compile_case(&mut self, form: &Form, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let (expr, clauses) = args.split_first().ok_or_else(|| form.error(ShispErrorType::MalformedForm(
+            "case expects `(case expr (patterns body...)...)`".to_string(),
+        )))?;
+        let word = self.compile_expr(expr)?;
+        let result = self.temp();
+
+        let mut seen: Vec<Pattern> = vec![];
+        let mut arms = vec![];
+        let mut has_else = false;
+        for clause in clauses {
+            let (head, body) = match clause.as_list() {
+                Some([head, body @ ..]) => (head, body),
+                _ => return Err(clause.error(ShispErrorType::MalformedForm(
+                    "case clauses must be `(patterns body...)`".to_string(),
+                ))),
+            };
+            let patterns = match &head.form_type {
+                FormType::Atom(name) if name == "else" => vec![Pattern::parse("*")],
+                FormType::List(forms) if !forms.is_empty() => forms
+                    .iter()
+                    .map(case_pattern)
+                    .collect::<Result<Vec<Pattern>, ShispError>>()?,
+                _ => vec![case_pattern(head)?],
+            };
+            if patterns.iter().all(|p| seen.iter().any(|earlier| p.covered_by(earlier))) {
+                self.warnings.push(ShispWarning::at(ShispWarningType::UnreachableClause, clause.row, clause.col));
+            }
+            has_else |= head.as_atom() == Some("else");
+
+            let (mut body, ()) = self.collect(|cg| {
+                let word = cg.compile_forms(body, context)?;
+                if !cg.ends_in_tail_call() {
+                    cg.emit(Command::assign(&result, word));
+                }
+                Ok(())
+            })?;
+            if body.is_empty() {
+                body.push(Command::simple(vec![Word::raw(":")]));
+            }
+            arms.push(CaseArm {
+                patterns: patterns.iter().map(Pattern::to_word).collect(),
+                body,
+            });
+            seen.extend(patterns);
+        }
+        if !has_else {
+            arms.push(CaseArm {
+                patterns: vec![Word::raw("*")],
+                body: vec![Command::assign(&result, nil())],
+            });
+        }
+        self.emit(Command::Case { word, arms });
+        Ok(Word::var(&result))
+    }
+
+    /// `(match expr (pattern body...)...)`, which evaluates the body of the first clause whose
+    /// pattern matches the value of `expr`, with the names of the pattern bound to the parts they
+    /// matched. Clauses after the first only run while `matched` is still nil.
+    fn compile_match(&mut self, form: &Form, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let (expr, clauses) = args.split_first().ok_or_else(|| form.error(ShispErrorType::MalformedForm(
+            "match expects `(match expr (pattern body...)...)`".to_string(),
+        )))?;
+        let value = self.compile_expr(expr)?;
+        let subject = self.temp();
+        self.emit(Command::assign(&subject, value));
+        let result = self.temp();
+        let matched = self.temp();
+
+        let exhaustive = clauses.iter().any(|c| c.as_list().and_then(|c| c.first()).is_some_and(irrefutable));
+        if !exhaustive {
+            self.warnings.push(ShispWarning::at(ShispWarningType::NonExhaustiveMatch, form.row, form.col));
+            self.emit(Command::assign(&result, nil()));
+        }
+        if clauses.len() > 1 {
+            self.emit(Command::assign(&matched, nil()));
+        }
+        for (i, clause) in clauses.iter().enumerate() {
+            let (pattern, body) = match clause.as_list() {
+                Some([pattern, body @ ..]) => (pattern, body),
+                _ => return Err(clause.error(ShispErrorType::MalformedForm(
+                    "match clauses must be `(pattern body...)`".to_string(),
+                ))),
+            };
+            let last = i + 1 == clauses.len();
+            let mut clause_body = |cg: &mut Codegen| {
+                if !last {
+                    cg.emit(Command::assign(&matched, truth()));
+                }
+                let word = cg.compile_forms(body, context)?;
+                if !cg.ends_in_tail_call() {
+                    cg.emit(Command::assign(&result, word));
+                }
+                Ok(())
+            };
+
+            self.env.push_frame();
+            let commands = self.collect(|cg| cg.compile_pattern(pattern, Word::var(&subject), &mut clause_body));
+            self.env.pop_frame();
+            let (commands, ()) = commands?;
+            if i == 0 {
+                self.block.extend(commands);
+            } else {
+                let unmatched = Command::simple(vec![
+                    Word::raw("["),
+                    Word::literal("-z"),
+                    Word::var(&matched),
+                    Word::raw("]"),
+                ]);
+                self.emit(Command::If {
+                    condition: vec![unmatched],
+                    then_body: commands,
+                    else_body: None,
+                });
+            }
+        }
+        Ok(Word::var(&result))
+    }
+
+    /// Emits the tests of `pattern` against `value`, and `body` where they all pass. Names bind the
+    /// value, `_` matches anything, literals compare equal, and `(p... . rest)` matches a list whose
+    /// first elements match the `p`s and whose remaining elements match `rest`.
+    fn compile_pattern(
+        &mut self,
+        pattern: &Form,
+        value: Word,
+        body: &mut dyn FnMut(&mut Codegen) -> Result<(), ShispError>,
+    ) -> Result<(), ShispError> {
+        let literal = match &pattern.form_type {
+            FormType::Atom(name) if name == "_" => return body(self),
+            FormType::Atom(name) if name != "." => {
+                let var = self.bind(name);
+                self.emit(Command::assign(&var, value));
+                return body(self);
+            }
+            FormType::Str(_) | FormType::Number(_) | FormType::Boolean(_) => self.compile_expr(pattern)?,
+            FormType::Quote(quoted) if quoted.as_atom().is_some() => self.compile_quote(quoted)?,
+            FormType::List(items) => {
+                let (elements, tail) = match &items[..] {
+                    [elements @ .., dot, tail] if dot.as_atom() == Some(".") && !elements.is_empty() => {
+                        (elements, Some(tail))
+                    }
+                    _ => (&items[..], None),
+                };
+                if elements.iter().chain(tail).any(|p| p.as_atom() == Some(".")) {
+                    return Err(pattern.error(ShispErrorType::MalformedForm(
+                        "`.` must come right before the last pattern of a list pattern".to_string(),
+                    )));
+                }
+                return self.compile_list_pattern(elements, tail, value, body);
+            }
+            _ => return Err(pattern.error(ShispErrorType::MalformedForm(
+                "patterns are names, `_`, literals, quoted symbols or lists of patterns".to_string(),
+            ))),
+        };
+        let test = Command::simple(vec![Word::raw("["), value, Word::raw("="), literal, Word::raw("]")]);
+        let (then_body, ()) = self.collect(body)?;
+        self.emit(Command::If {
+            condition: vec![test],
+            then_body,
+            else_body: None,
+        });
+        Ok(())
+    }
+
+    fn compile_list_pattern(
+        &mut self,
+        elements: &[Form],
+        tail: Option<&Form>,
+        value: Word,
+        body: &mut dyn FnMut(&mut Codegen) -> Result<(), ShispError>,
+    ) -> Result<(), ShispError> {
+        let (first, rest) = match (elements.split_first(), tail) {
+            (Some(split), _) => split,
+            (None, Some(tail)) => return self.compile_pattern(tail, value, body),
+            (None, None) => {
+                let (then_body, ()) = self.collect(body)?;
+                self.emit(Command::If {
+                    condition: vec![Command::simple(vec![Word::raw("["), Word::literal("-z"), value, Word::raw("]")])],
+                    then_body,
+                    else_body: None,
+                });
+                return Ok(());
+            }
+        };
+
+        let (cell_body, ()) = self.collect(|cg| {
+            let car = cg.temp();
+            cg.emit(Command::call("shisp_car", vec![value.clone()]));
+            cg.emit(Command::assign(&car, Word::var("shisp_r")));
+            let cdr = cg.temp();
+            cg.emit(Command::call("shisp_cdr", vec![value.clone()]));
+            cg.emit(Command::assign(&cdr, Word::var("shisp_r")));
+            cg.compile_pattern(first, Word::var(&car), &mut |cg: &mut Codegen| {
+                cg.compile_list_pattern(rest, tail, Word::var(&cdr), body)
+            })
+        })?;
+        self.emit(Command::Case {
+            word: value,
+            arms: vec![CaseArm {
+                patterns: vec![Word::raw("shisp_c[0-9]*")],
+                body: cell_body,
+            }],
+        });
+        Ok(())
+    }
+
+    fn compile_map_form(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let (_, sh_name, arity) = MAP_FORMS.iter().find(|(map_form, _, _)| *map_form == name).unwrap();
+        if args.len() != *arity {
+            return Err(form.error(ShispErrorType::ArityMismatch(name.to_string(), *arity, args.len())));
+        }
+        let words = args
+            .iter()
+            .map(|f| self.compile_expr(f))
+            .collect::<Result<Vec<Word>, ShispError>>()?;
+        self.maps = true;
+        self.emit(Command::call(sh_name, words));
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word::var("shisp_r")));
+        Ok(Word::var(&result))
+    }
+
+    /// `(print args...)`, `(println args...)` and `(eprintln args...)`, which write their arguments
+    /// separated by spaces, the last two with a newline and `eprintln` to stderr.
+    fn compile_print(&mut self, name: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let mut words = vec![];
+        for arg in args {
+            words.push(self.compile_expr(arg)?);
+        }
+        let mut printf = vec!["%s"; words.len()].join(" ");
+        if name != "print" {
+            printf.push_str("\\n");
+        }
+        words.insert(0, Word::literal(&printf));
+        let command = Command::call("printf", words);
+        self.emit(match name {
+            "eprintln" => command.redirect(Redirect {
+                fd: None,
+                op: RedirectOp::DupOutput,
+                target: Word::literal("2"),
+            }),
+            _ => command,
+        });
+        Ok(nil())
+    }
+
+    /// `(format "text" args...)`, whose value is `text` with the `~` directives replaced by `args`.
+    /// When the text can end in a newline, `printf` is followed by a character that is removed
+    /// again, as the command substitution would drop the newline.
+    fn compile_format(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (text, args) = match args.split_first() {
+            Some((Form { form_type: FormType::Str(text), .. }, args)) => (text, args),
+            _ => return Err(form.error(ShispErrorType::MalformedForm(
+                "format expects `(format \"text\" args...)` with a literal string".to_string(),
+            ))),
+        };
+        let format = format::parse(text).map_err(|e| form.error(ShispErrorType::MalformedForm(e)))?;
+        if args.len() != format.arguments {
+            return Err(form.error(ShispErrorType::ArityMismatch(
+                format!("format \"{}\"", text),
+                format.arguments,
+                args.len(),
+            )));
+        }
+
+        let mut words = vec![Word::literal(&format.printf)];
+        if format.printf.starts_with('-') {
+            words.insert(0, Word::literal("--"));
+        }
+        for arg in args {
+            words.push(self.compile_expr(arg)?);
+        }
+        let mut commands = vec![Command::call("printf", words)];
+        if format.trailing_newline {
+            commands.push(Command::call("printf", vec![Word::literal(".")]));
+        }
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word(vec![WordPart::CommandSubst(commands)])));
+        if format.trailing_newline {
+            self.emit(Command::assign(&result, Word(vec![WordPart::Param(format!("{}%.", result))])));
+        }
+        Ok(Word::var(&result))
+    }
+
+    /// `(heredoc program args... "text")`, which runs the program with `text` as its standard input,
+    /// taken literally. `heredoc-interpolate` replaces the `,name` references in the text with the
+    /// values of the variables, as `shell-literal` does. An argument that is a literal string
+    /// starting with `>` or `>>` writes the output to the file named after it instead.
+    fn compile_heredoc(
+        &mut self,
+        form: &Form,
+        args: &[Form],
+        interpolate: bool,
+        context: Context,
+    ) -> Result<Word, ShispError> {
+        let malformed = |message: &str| form.error(ShispErrorType::MalformedForm(message.to_string()));
+        let (text, args) = match args.split_last() {
+            Some((Form { form_type: FormType::Str(text), .. }, args)) if !args.is_empty() => (text, args),
+            _ => return Err(malformed("heredoc expects `(heredoc program args... \"text\")`")),
+        };
+
+        let mut words = vec![];
+        let mut redirects = vec![];
+        for arg in args {
+            match &arg.form_type {
+                FormType::Str(redirect) if redirect.starts_with('>') => {
+                    let (op, path) = match redirect.strip_prefix(">>") {
+                        Some(path) => (RedirectOp::Append, path.trim()),
+                        None => (RedirectOp::Output, redirect[1..].trim()),
+                    };
+                    if path.is_empty() {
+                        return Err(arg.error(ShispErrorType::MalformedForm(
+                            "the redirection of a heredoc needs a file name".to_string(),
+                        )));
+                    }
+                    redirects.push(Redirect {
+                        fd: None,
+                        op,
+                        target: Word::literal(path),
+                    });
+                }
+                _ => words.push(self.compile_expr(arg)?),
+            }
+        }
+        if words.is_empty() {
+            return Err(malformed("heredoc expects a program to run"));
+        }
+
+        let mut body = if interpolate {
+            let env = &self.env;
+            shell_literal::interpolate_heredoc(text, |name| env.lookup(name).cloned()).map_err(|e| form.error(e))?
+        } else {
+            text.clone()
+        };
+        if !body.is_empty() && !body.ends_with('\n') {
+            body.push('\n');
+        }
+        let command = Command::HereDoc {
+            command: Box::new(Command::Simple {
+                assignments: vec![],
+                words,
+                redirects,
+            }),
+            delimiter: heredoc_delimiter(&body),
+            quoted: !interpolate,
+            body,
+        };
+        Ok(self.command_value(command, context))
+    }
+
+    /// The variable holding the value of `word`, which is copied to a temp unless it is a variable.
+    fn stored(&mut self, word: Word) -> String {
+        match word.as_var() {
+            Some(var) => var.to_string(),
+            None => {
+                let temp = self.temp();
+                self.emit(Command::assign(&temp, word));
+                temp
+            }
+        }
+    }
+
+    /// `(substring string start)` or `(substring string start end)`, the characters of `string`
+    /// from index `start` up to `end` excluded, or up to its end.
+    fn compile_substring(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        if !(2..=3).contains(&args.len()) {
+            return Err(form.error(ShispErrorType::ArityMismatch("substring".to_string(), 3, args.len())));
+        }
+        let mut words = args
+            .iter()
+            .map(|f| self.compile_expr(f))
+            .collect::<Result<Vec<Word>, ShispError>>()?;
+        if words.len() == 2 {
+            words.push(nil());
+        }
+        self.emit(Command::call("shisp_substring", words));
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word::var("shisp_r")));
+        Ok(Word::var(&result))
+    }
+
+    /// `(string-prefix? string prefix)` and `(string-suffix? string suffix)`, compiled to a `case`
+    /// whose pattern quotes the prefix or suffix so that it only matches itself.
+    fn compile_affix_test(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let (string, affix) = match args {
+            [string, affix] => (self.compile_expr(string)?, self.compile_expr(affix)?),
+            _ => return Err(form.error(ShispErrorType::ArityMismatch(name.to_string(), 2, args.len()))),
+        };
+        let mut pattern = affix.0;
+        if name == "string-prefix?" {
+            pattern.push(WordPart::Raw("*".to_string()));
+        } else {
+            pattern.insert(0, WordPart::Raw("*".to_string()));
+        }
+        let result = self.temp();
+        self.emit(Command::Case {
+            word: string,
+            arms: vec![
+                CaseArm {
+                    patterns: vec![Word(pattern)],
+                    body: vec![Command::assign(&result, truth())],
+                },
+                CaseArm {
+                    patterns: vec![Word::raw("*")],
+                    body: vec![Command::assign(&result, nil())],
+                },
+            ],
+        });
+        Ok(Word::var(&result))
+    }
+
+    /// `(string-upcase string)` and `(string-downcase string)`, with the `${var^^}` and `${var,,}`
+    /// expansions where the target has them and `tr` otherwise.
+    fn compile_case_change(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let word = match args {
+            [string] => self.compile_expr(string)?,
+            _ => return Err(form.error(ShispErrorType::ArityMismatch(name.to_string(), 1, args.len()))),
+        };
+        let up = name == "string-upcase";
+        let result = self.temp();
+        if self.uses(Feature::CaseModifications) {
+            let var = self.stored(word);
+            let expansion = format!("{}{}", var, if up { "^^" } else { ",," });
+            self.emit(Command::assign(&result, Word(vec![WordPart::Param(expansion)])));
+            return Ok(Word::var(&result));
+        }
+
+        // The `.` keeps the newlines the string ends with from being dropped by the substitution.
+        let (from, to) = if up { ("[:lower:]", "[:upper:]") } else { ("[:upper:]", "[:lower:]") };
+        let translate = Command::Pipeline {
+            negated: false,
+            commands: vec![
+                Command::call("printf", vec![Word::literal("%s."), word]),
+                Command::call("tr", vec![Word::literal(from), Word::literal(to)]),
+            ],
+        };
+        self.emit(Command::assign(&result, Word(vec![WordPart::CommandSubst(vec![translate])])));
+        self.emit(Command::assign(&result, Word(vec![WordPart::Param(format!("{}%.", result))])));
+        Ok(Word::var(&result))
+    }
+
+    /// `(while test body...)`, which evaluates `body` for as long as `test` is true.
+    fn compile_while(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (test, body) = args.split_first().ok_or_else(|| form.error(ShispErrorType::MalformedForm(
+            "while expects `(while test body...)`".to_string(),
+        )))?;
+        let (commands, test) = self.collect(|cg| cg.compile_test(test))?;
+        let (body, ()) = self.collect(|cg| cg.compile_statements(body))?;
+        if commands.is_empty() {
+            self.emit(Command::While {
+                until: false,
+                condition: vec![test],
+                body,
+            });
+            return Ok(nil());
+        }
+
+        // `set -e` does not apply in the condition of a loop, where a `try` would not stop at a
+        // failure, so the commands the test needs run at the start of the body instead.
+        let mut loop_body = commands;
+        loop_body.push(Command::If {
+            condition: vec![Command::Pipeline {
+                negated: true,
+                commands: vec![test],
+            }],
+            then_body: vec![Command::simple(vec![Word::raw("break")])],
+            else_body: None,
+        });
+        loop_body.extend(body);
+        self.emit(Command::While {
+            until: false,
+            condition: vec![Command::simple(vec![Word::raw(":")])],
+            body: loop_body,
+        });
+        Ok(nil())
+    }
+
+    /// `(dotimes (var count) body...)`, which evaluates `body` with `var` bound to each number from
+    /// 0 up to `count`, excluded.
+    fn compile_dotimes(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (name, count, body) = loop_parts(form, args)?;
+        let count = self.compile_expr(count)?;
+        let limit = self.temp();
+        self.emit(Command::assign(&limit, count));
+
+        self.env.push_frame();
+        let var = self.bind(name);
+        self.emit(Command::assign(&var, Word::literal("0")));
+        let body = self.collect(|cg| {
+            cg.compile_statements(body)?;
+            cg.emit(Command::assign(&var, Word(vec![WordPart::Arith(format!("{} + 1", var))])));
+            Ok(())
+        });
+        self.env.pop_frame();
+
+        self.emit(Command::While {
+            until: false,
+            condition: vec![Command::simple(vec![
+                Word::raw("["),
+                Word::var(&var),
+                Word::literal("-lt"),
+                Word::var(&limit),
+                Word::raw("]"),
+            ])],
+            body: body?.0,
+        });
+        Ok(nil())
+    }
+
+    /// `(for-each (var list) body...)`, which evaluates `body` with `var` bound to each element of
+    /// `list` in turn.
+    fn compile_for_each(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (name, list, body) = loop_parts(form, args)?;
+        let list = self.compile_expr(list)?;
+        let rest = self.temp();
+        self.emit(Command::assign(&rest, list));
+
+        self.env.push_frame();
+        let var = self.bind(name);
+        let body = self.collect(|cg| {
+            cg.emit(Command::call("shisp_car", vec![Word::var(&rest)]));
+            cg.emit(Command::assign(&var, Word::var("shisp_r")));
+            cg.compile_statements(body)?;
+            cg.emit(Command::call("shisp_cdr", vec![Word::var(&rest)]));
+            cg.emit(Command::assign(&rest, Word::var("shisp_r")));
+            Ok(())
+        });
+        self.env.pop_frame();
+
+        let condition = vec![self.test_true(Word::var(&rest))];
+        self.emit(Command::While {
+            until: false,
+            condition,
+            body: body?.0,
+        });
+        Ok(nil())
+    }
+
+    /// `(for-each-line (command...) (var) body...)`, which evaluates `body` with `var` bound to each
+    /// line the command writes, including a last line without a newline. The command is anything a
+    /// `pipe` stage can be, and as in a pipeline, the loop runs in a subshell.
+    fn compile_for_each_line(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (source, name, body) = match args {
+            [source, var, body @ ..] => match var.as_list() {
+                Some([name]) if name.as_atom().is_some() => (source, name.as_atom().unwrap(), body),
+                _ => return Err(var.error(ShispErrorType::MalformedForm(
+                    "for-each-line expects the line variable as `(name)`".to_string(),
+                ))),
+            },
+            _ => return Err(form.error(ShispErrorType::MalformedForm(
+                "for-each-line expects `(for-each-line (command...) (var) body...)`".to_string(),
+            ))),
+        };
+        let source = self.compile_stage(source)?;
+
+        self.env.push_frame();
+        let var = self.bind(name);
+        let cleanups = self.cleanup_count;
+        let body = self.collect(|cg| cg.compile_statements(body));
+        self.env.pop_frame();
+        let mut body = body?.0;
+        if self.cleanup_count != cleanups {
+            // The loop runs in a subshell, which must not run the cleanups of the code around it.
+            body.insert(0, Command::assign("shisp_unwind", nil()));
+        }
+
+        let read = Command::Simple {
+            assignments: vec![("IFS".to_string(), Word::literal(""))],
+            words: vec![Word::literal("read"), Word::literal("-r"), Word::literal(&var)],
+            redirects: vec![],
+        };
+        let last_line = self.test_true(Word::var(&var));
+        let loop_command = Command::While {
+            until: false,
+            condition: vec![Command::AndOr {
+                first: Box::new(read),
+                rest: vec![(AndOrOp::Or, last_line)],
+            }],
+            body,
+        };
+        self.emit(Command::Pipeline {
+            negated: false,
+            commands: vec![source, loop_command],
+        });
+        Ok(nil())
+    }
+
+    /// `(try body... (catch status handler...))`, which runs `body` in a subshell with `set -e` and,
+    /// if it fails, evaluates `handler` with `status` bound to its exit status. The value is the
+    /// value of the handler, or true when the body succeeded. Without a `catch`, a failure gives nil.
+    fn compile_try(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (body, catch) = match args.split_last() {
+            Some((last, body)) if last.head() == Some("catch") => match last.as_list() {
+                Some([_, status, handler @ ..]) if status.as_atom().is_some() => {
+                    (body, Some((status.as_atom().unwrap(), handler)))
+                }
+                _ => return Err(last.error(ShispErrorType::MalformedForm(
+                    "catch expects `(catch status handler...)`".to_string(),
+                ))),
+            },
+            _ => (args, None),
+        };
+        if body.is_empty() {
+            return Err(form.error(ShispErrorType::MalformedForm(
+                "try expects `(try body... (catch status handler...))`".to_string(),
+            )));
+        }
+
+        let errexit = std::mem::replace(&mut self.errexit, true);
+        let body = self.collect(|cg| {
+            // The subshell has no traps, so the cleanups of the code around it are not its own.
+            cg.emit(Command::assign("shisp_unwind", nil()));
+            cg.emit(Command::call("set", vec![Word::literal("-e")]));
+            cg.compile_statements(body)
+        });
+        self.errexit = errexit;
+        let (body, ()) = body?;
+
+        let status = self.run_for_status(Command::Subshell(body));
+
+        let result = self.temp();
+        self.env.push_frame();
+        let handler = self.collect(|cg| match catch {
+            Some((name, handler)) => {
+                let var = cg.bind(name);
+                cg.emit(Command::assign(&var, Word::var(&status)));
+                let word = cg.compile_body(handler)?;
+                cg.emit(Command::assign(&result, word));
+                Ok(())
+            }
+            None => {
+                cg.emit(Command::assign(&result, nil()));
+                Ok(())
+            }
+        });
+        self.env.pop_frame();
+        self.emit(Command::If {
+            condition: vec![Command::simple(vec![
+                Word::raw("["),
+                Word::var(&status),
+                Word::literal("-ne"),
+                Word::literal("0"),
+                Word::raw("]"),
+            ])],
+            then_body: handler?.0,
+            else_body: Some(vec![Command::assign(&result, truth())]),
+        });
+        Ok(Word::var(&result))
+    }
+
+    /// `(unwind-protect body cleanup...)`, which evaluates `body` and then `cleanup`, even when the
+    /// script exits or is interrupted before `body` is done. The cleanup becomes a function of its
+    /// own, kept on a stack the runtime runs from an `EXIT` trap, so nested uses run their cleanups
+    /// innermost first. The value is the value of `body`.
+    fn compile_unwind_protect(&mut self, form: &Form, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let (body, cleanup) = args.split_first().ok_or_else(|| form.error(ShispErrorType::MalformedForm(
+            "unwind-protect expects `(unwind-protect body cleanup...)`".to_string(),
+        )))?;
+
+        self.cleanup_count += 1;
+        let name = format!("shisp_cleanup{}", self.cleanup_count);
+        let (cleanup, ()) = self.collect(|cg| cg.compile_statements(cleanup))?;
+        let (definition, ()) = self.collect(|cg| {
+            cg.emit(Command::FunctionDef {
+                name: name.clone(),
+                body: cleanup,
+            });
+            Ok(())
+        })?;
+        self.lifted.extend(definition);
+
+        self.emit(Command::call("shisp_protect", vec![Word::literal(&name)]));
+        let word = self.compile_in(body, Context { tail: false, ..context })?;
+        if context.discarded {
+            self.emit(Command::call("shisp_unprotect", vec![]));
+            return Ok(nil());
+        }
+        let result = self.temp();
+        self.emit(Command::assign(&result, word));
+        self.emit(Command::call("shisp_unprotect", vec![]));
+        Ok(Word::var(&result))
+    }
+
+    /// `(getenv name default)`, the value of the environment variable `name`, or of `default` when it
+    /// is unset or empty. The default is only evaluated when it is needed.
+    fn compile_getenv(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (name, default) = match args {
+            [name] => (environment_name(name)?, None),
+            [name, default] => (environment_name(name)?, Some(default)),
+            _ => return Err(form.error(ShispErrorType::MalformedForm(
+                "getenv expects `(getenv name)` or `(getenv name default)`".to_string(),
+            ))),
+        };
+        let value = Word(vec![WordPart::Param(format!("{}-", name))]);
+        let default = match default {
+            Some(default) => default,
+            None => return Ok(value),
+        };
+
+        let result = self.temp();
+        self.emit(Command::assign(&result, value));
+        let (mut then_body, word) = self.collect(|cg| cg.compile_expr(default))?;
+        then_body.push(Command::assign(&result, word));
+        self.emit(Command::If {
+            condition: vec![Command::simple(vec![
+                Word::raw("["),
+                Word::literal("-z"),
+                Word::var(&result),
+                Word::raw("]"),
+            ])],
+            then_body,
+            else_body: None,
+        });
+        Ok(Word::var(&result))
+    }
+
+    /// `(setenv! name value)` and `(export! name [value])`, which both export the environment
+    /// variable `name` to the programs the script runs, setting it to `value` if given.
+    fn compile_setenv(&mut self, form: &Form, op: &str, args: &[Form]) -> Result<Word, ShispError> {
+        let (name, value) = match (op, args) {
+            ("setenv!", [name, value]) | ("export!", [name, value]) => (environment_name(name)?, Some(value)),
+            ("export!", [name]) => (environment_name(name)?, None),
+            _ => return Err(form.error(ShispErrorType::MalformedForm(format!(
+                "{} expects `({} name value)`{}",
+                op,
+                op,
+                if op == "export!" { " or `(export! name)`" } else { "" }
+            )))),
+        };
+
+        let word = match value {
+            Some(value) => {
+                let word = self.compile_expr(value)?;
+                self.emit(Command::assign(&name, word.clone()));
+                word
+            }
+            None => Word(vec![WordPart::Param(format!("{}-", name))]),
+        };
+        self.emit(Command::call("export", vec![Word::literal(&name)]));
+        Ok(word)
+    }
+
+    /// `(defcli "description" specs...)`, which parses the arguments of the script into the global
+    /// variables the specs name, with a `while`/`case` loop over the options followed by the
+    /// positional arguments. `-h` and `--help` print a help text generated from the specs.
+    fn compile_defcli(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        if !self.env.is_global() {
+            return Err(form.error(ShispErrorType::MalformedForm(
+                "defcli can only be used at the top level".to_string(),
+            )));
+        }
+        let cli = cli::parse(form, args)?;
+
+        let mut arms = vec![CaseArm {
+            patterns: vec![Word::literal("-h"), Word::literal("--help")],
+            body: vec![
+                Command::call("printf", vec![Word::literal(&cli.help()), program_name()]),
+                Command::call("exit", vec![Word::literal("0")]),
+            ],
+        }];
+        for param in &cli.params {
+            let var = self.env.bind_global(param.name);
+            let patterns = param.switches.iter().map(|s| Word::literal(s)).collect::<Vec<Word>>();
+            match &param.kind {
+                Kind::Flag => {
+                    self.emit(Command::assign(&var, nil()));
+                    arms.push(CaseArm {
+                        patterns,
+                        body: vec![Command::assign(&var, truth())],
+                    });
+                }
+                Kind::Option(default) => {
+                    let word = self.compile_expr(default)?;
+                    self.emit(Command::assign(&var, word));
+                    let mut body = vec![Command::If {
+                        condition: vec![argument_count("-lt", "2")],
+                        then_body: cli_error("%s: %s expects a value\\n", vec![Word::raw("\"$1\"")]),
+                        else_body: None,
+                    }];
+                    body.push(Command::simple(vec![Word::raw("shift")]));
+                    body.push(Command::assign(&var, positional(1)));
+                    arms.push(CaseArm { patterns, body });
+
+                    let long = param.switches.iter().filter(|s| s.starts_with("--"));
+                    let with_value =
+                        |s: &String| Word(vec![WordPart::Literal(format!("{}=", s)), WordPart::Raw("*".to_string())]);
+                    arms.push(CaseArm {
+                        patterns: long.map(with_value).collect(),
+                        body: vec![Command::assign(&var, Word(vec![WordPart::Param("1#*=".to_string())]))],
+                    });
+                }
+                Kind::Arg | Kind::Rest => {}
+            }
+        }
+        arms.retain(|arm| !arm.patterns.is_empty());
+        arms.push(CaseArm {
+            patterns: vec![Word::literal("--")],
+            body: vec![Command::simple(vec![Word::raw("shift")]), Command::simple(vec![Word::raw("break")])],
+        });
+        arms.push(CaseArm {
+            patterns: vec![Word::raw("-?*")],
+            body: cli_error("%s: unknown option %s (see --help)\\n", vec![Word::raw("\"$1\"")]),
+        });
+        arms.push(CaseArm {
+            patterns: vec![Word::raw("*")],
+            body: vec![Command::simple(vec![Word::raw("break")])],
+        });
+        self.emit(Command::While {
+            until: false,
+            condition: vec![argument_count("-gt", "0")],
+            body: vec![
+                Command::Case { word: positional(1), arms },
+                Command::simple(vec![Word::raw("shift")]),
+            ],
+        });
+
+        let mut rest = false;
+        for param in &cli.params {
+            let var = self.env.lookup(param.name).unwrap().clone();
+            match param.kind {
+                Kind::Arg => {
+                    self.emit(Command::If {
+                        condition: vec![argument_count("-eq", "0")],
+                        then_body: cli_error(
+                            "%s: missing %s (see --help)\\n",
+                            vec![Word::literal(&format!("<{}>", param.name))],
+                        ),
+                        else_body: None,
+                    });
+                    self.emit(Command::assign(&var, positional(1)));
+                    self.emit(Command::simple(vec![Word::raw("shift")]));
+                }
+                Kind::Rest => {
+                    rest = true;
+                    self.emit(Command::call("shisp_list", vec![Word::raw("\"$@\"")]));
+                    self.emit(Command::assign(&var, Word::var("shisp_r")));
+                }
+                Kind::Flag | Kind::Option(_) => {}
+            }
+        }
+        if !rest {
+            self.emit(Command::If {
+                condition: vec![argument_count("-gt", "0")],
+                then_body: cli_error("%s: unexpected argument %s (see --help)\\n", vec![Word::raw("\"$1\"")]),
+                else_body: None,
+            });
+        }
+        Ok(nil())
+    }
+
+    /// The command testing `test` in a `cond` clause or a `while` loop. A pipeline or redirection
+    /// is run beforehand rather than in the condition, where `set -e` would not apply to the code
+    /// inside it, and the test is on its exit status.
+    fn compile_test(&mut self, test: &Form) -> Result<Command, ShispError> {
+        match test.head() {
+            Some("pipe" | ">" | ">>" | "<" | "2>" | "2>>" | "2>&1") => {
+                // Its own failure is the test's, so `set -e` stays off inside it.
+                let errexit = std::mem::replace(&mut self.errexit, false);
+                let command = self.compile_condition(test);
+                self.errexit = errexit;
+                let status = self.run_for_status(command?);
+                Ok(Command::simple(vec![
+                    Word::raw("["),
+                    Word::var(&status),
+                    Word::literal("-eq"),
+                    Word::literal("0"),
+                    Word::raw("]"),
+                ]))
+            }
+            _ => self.compile_condition(test),
+        }
+    }
+
+    /// Runs `command` as a plain command, returning the variable holding its exit status. A
+    /// failure would end the script under `set -e`, and in a condition `set -e` would not apply
+    /// inside it, so `set -e` is turned off around it instead.
+    fn run_for_status(&mut self, command: Command) -> String {
+        if self.errexit {
+            self.emit(Command::call("set", vec![Word::literal("+e")]));
+        }
+        self.emit(command);
+        let status = self.temp();
+        self.emit(Command::assign(&status, Word::raw("$?")));
+        if self.errexit {
+            self.emit(Command::call("set", vec![Word::literal("-e")]));
+        }
+        status
+    }
+
+    /// Compiles `test` to a command that succeeds when it is true. Pipelines and redirections are
+    /// tested by their exit status directly, anything else by its value.
+    fn compile_condition(&mut self, test: &Form) -> Result<Command, ShispError> {
+        let args = test.as_list().map(|l| &l[1..]).unwrap_or_default();
+        match test.head() {
+            Some("pipe") => self.pipeline(test, args),
+            Some("run") => self.run_command(test, args),
+            Some(name) if FILE_TESTS.iter().any(|(file_test, _)| *file_test == name) => {
+                self.file_test(test, name, args)
+            }
+            Some(op @ (">" | ">>" | "<" | "2>" | "2>>" | "2>&1")) => {
+                let (redirect, inner) = self.redirection(test, op, args)?;
+                let (mut commands, condition) = self.collect(|cg| cg.compile_condition(inner))?;
+                commands.push(condition);
+                Ok(Command::group(commands).redirect(redirect))
+            }
+            _ => {
+                let word = self.compile_in(test, Context { tested: true, ..Context::default() })?;
+                Ok(self.test_true(word))
+            }
+        }
+    }
+
+    /// The `test` command of a file predicate such as `(directory? path)`.
+    fn file_test(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Command, ShispError> {
+        let (_, op) = FILE_TESTS.iter().find(|(test, _)| *test == name).unwrap();
+        let path = match args {
+            [path] => self.compile_expr(path)?,
+            _ => return Err(form.error(ShispErrorType::ArityMismatch(name.to_string(), 1, args.len()))),
+        };
+        Ok(Command::simple(vec![Word::raw("["), Word::literal(op), path, Word::raw("]")]))
+    }
+
+    /// Runs `command`, giving a value that is true when it succeeds.
+    fn succeeds(&mut self, command: Command) -> Word {
+        let result = self.temp();
+        self.emit(Command::If {
+            condition: vec![command],
+            then_body: vec![Command::assign(&result, truth())],
+            else_body: Some(vec![Command::assign(&result, nil())]),
+        });
+        Word::var(&result)
+    }
+
+    /// A command whose value is true when it succeeds. When the value is not used, the command is
+    /// run on its own so that its failure counts under `set -e` and in `try`. When it is the value
+    /// of a function, the function returns its exit status, so that a failure still counts where
+    /// the function is called under `set -e`.
+    fn command_value(&mut self, command: Command, context: Context) -> Word {
+        if context.discarded {
+            self.emit(command);
+            return nil();
+        }
+        if !context.tail {
+            return self.succeeds(command);
+        }
+        let status = match self.status.clone() {
+            Some(status) => status,
+            None => {
+                let status = self.temp();
+                self.status = Some(status.clone());
+                status
+            }
+        };
+        let result = self.temp();
+        self.emit(Command::If {
+            condition: vec![command],
+            then_body: vec![Command::assign(&result, truth())],
+            else_body: Some(vec![
+                Command::assign(&status, Word::raw("$?")),
+                Command::assign(&result, nil()),
+            ]),
+        });
+        Word::var(&result)
+    }
+
+    /// `(pipe stage...)`, whose value is true when the pipeline succeeds.
+    fn compile_pipe(&mut self, form: &Form, stages: &[Form], context: Context) -> Result<Word, ShispError> {
+        let pipeline = self.pipeline(form, stages)?;
+        Ok(self.command_value(pipeline, context))
+    }
+
+    /// `(run program args...)`, whose value is true when the program succeeds.
+    fn compile_run(&mut self, form: &Form, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let command = self.run_command(form, args)?;
+        Ok(self.command_value(command, context))
+    }
+
+    /// `(capture program args...)` or `(capture (pipe ...))`, whose value is what the command
+    /// writes to its standard output, without the newlines it ends with.
+    fn compile_capture(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let (mut commands, command) = self.collect(|cg| cg.command_form(form, args))?;
+        commands.push(command);
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word(vec![WordPart::CommandSubst(commands)])));
+        Ok(Word::var(&result))
+    }
+
+    /// `(status program args...)` or `(status (pipe ...))`, whose value is the exit status of the
+    /// command.
+    fn compile_status(&mut self, form: &Form, args: &[Form]) -> Result<Word, ShispError> {
+        let command = self.command_form(form, args)?;
+        Ok(self.exit_status(command))
+    }
+
+    /// The exit status of `command`, run so that its failure never stops the script.
+    fn exit_status(&mut self, command: Command) -> Word {
+        let result = self.temp();
+        self.emit(Command::If {
+            condition: vec![command],
+            then_body: vec![Command::assign(&result, Word::literal("0"))],
+            else_body: Some(vec![Command::assign(&result, Word::raw("$?"))]),
+        });
+        Word::var(&result)
+    }
+
+    /// `(spawn expr)`, which evaluates `expr` in the background, in a subshell. The value is the
+    /// process ID of the job, for `wait`.
+    fn compile_spawn(&mut self, job: &Form) -> Result<Word, ShispError> {
+        let cleanups = self.cleanup_count;
+        let (mut commands, ()) = self.collect(|cg| cg.compile_statements(std::slice::from_ref(job)))?;
+        if self.cleanup_count != cleanups {
+            // The job runs in a subshell, which must not run the cleanups of the code around it.
+            commands.insert(0, Command::assign("shisp_unwind", nil()));
+        }
+        self.emit(Command::Background(Box::new(Command::group(commands))));
+        let pid = self.temp();
+        self.emit(Command::assign(&pid, Word::raw("$!")));
+        Ok(Word::var(&pid))
+    }
+
+    /// `(parallel expr...)`, which evaluates every `expr` as a background job and waits for all of
+    /// them. It fails, with the exit status of the last job that failed, if any of them does.
+    fn compile_parallel(&mut self, jobs: &[Form], context: Context) -> Result<Word, ShispError> {
+        let pids = jobs
+            .iter()
+            .map(|job| self.compile_spawn(job))
+            .collect::<Result<Vec<Word>, ShispError>>()?;
+        let status = self.temp();
+        self.emit(Command::assign(&status, Word::literal("0")));
+        for pid in pids {
+            self.emit(Command::AndOr {
+                first: Box::new(Command::call("wait", vec![pid])),
+                rest: vec![(AndOrOp::Or, Command::assign(&status, Word::raw("$?")))],
+            });
+        }
+        Ok(self.command_value(Command::call("shisp_return", vec![Word::var(&status)]), context))
+    }
+
+    /// The command `capture` and `status` run: a program and its arguments, or a single form such
+    /// as a `pipe` or `run`. Other forms give the status of a `cond` test on their value.
+    fn command_form(&mut self, form: &Form, args: &[Form]) -> Result<Command, ShispError> {
+        match args {
+            [inner] if inner.as_list().is_some() => self.compile_condition(inner),
+            _ => self.run_command(form, args),
+        }
+    }
+
+    /// The command running the program `args[0]` with the rest of `args` as its arguments.
+    fn run_command(&mut self, form: &Form, args: &[Form]) -> Result<Command, ShispError> {
+        if args.is_empty() {
+            return Err(form.error(ShispErrorType::MalformedForm(format!(
+                "{} expects a program to run",
+                form.head().unwrap_or_default()
+            ))));
+        }
+        let words = args
+            .iter()
+            .map(|arg| self.compile_expr(arg))
+            .collect::<Result<Vec<Word>, ShispError>>()?;
+        Ok(Command::simple(words))
+    }
+
+    fn pipeline(&mut self, form: &Form, stages: &[Form]) -> Result<Command, ShispError> {
+        if stages.is_empty() {
+            return Err(form.error(ShispErrorType::MalformedForm(
+                "pipe expects at least one command".to_string(),
+            )));
+        }
+        let mut commands = stages
+            .iter()
+            .map(|stage| self.compile_stage(stage))
+            .collect::<Result<Vec<Command>, ShispError>>()?;
+        if commands.len() == 1 {
+            return Ok(commands.remove(0));
+        }
+        Ok(Command::Pipeline {
+            negated: false,
+            commands,
+        })
+    }
+
+    /// Compiles a stage of a pipeline. A list headed by a Shisp function calls it, one headed by
+    /// any other name that isn't a special form runs that program, and anything else is run as a
+    /// group of the commands computing it.
+    fn compile_stage(&mut self, stage: &Form) -> Result<Command, ShispError> {
+        let args = match stage.as_list() {
+            Some([_, args @ ..]) => args,
+            _ => return Err(stage.error(ShispErrorType::MalformedForm(
+                "pipe stages must be commands, such as `(grep \"x\")`".to_string(),
+            ))),
+        };
+
+        match stage.head() {
+            Some("run") => self.run_command(stage, args),
+            Some(name) if !SPECIAL_FORMS.contains(&name) => {
+                let command = match self.env.lookup_function(name) {
+                    Some(_) => self.call_command(stage, name, args)?,
+                    None => {
+                        let mut words = vec![Word::literal(name)];
+                        for arg in args {
+                            words.push(self.compile_expr(arg)?);
+                        }
+                        Command::simple(words)
+                    }
+                };
+                Ok(command)
+            }
+            _ => {
+                let (commands, _) = self.collect(|cg| cg.compile_expr(stage))?;
+                Ok(Command::group(commands))
+            }
+        }
+    }
+
+    /// `(> target expr)` and friends, which evaluate `expr` with its output or input redirected.
+    fn compile_redirect(&mut self, form: &Form, op: &str, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        let (redirect, inner) = self.redirection(form, op, args)?;
+        let (commands, word) = self.collect(|cg| cg.compile_in(inner, Context { tail: false, ..context }))?;
+        self.emit(Command::group(commands).redirect(redirect));
+        Ok(word)
+    }
+
+    fn redirection<'a>(&mut self, form: &Form, op: &str, args: &'a [Form]) -> Result<(Redirect, &'a Form), ShispError> {
+        if op == "2>&1" {
+            return match args {
+                [inner] => Ok((
+                    Redirect {
+                        fd: Some(2),
+                        op: RedirectOp::DupOutput,
+                        target: Word::literal("1"),
+                    },
+                    inner,
+                )),
+                _ => Err(form.error(ShispErrorType::ArityMismatch(op.to_string(), 1, args.len()))),
+            };
+        }
+
+        let (target, inner) = match args {
+            [target, inner] => (target, inner),
+            _ => return Err(form.error(ShispErrorType::ArityMismatch(op.to_string(), 2, args.len()))),
+        };
+        let (fd, op) = match op {
+            ">" => (None, RedirectOp::Output),
+            ">>" => (None, RedirectOp::Append),
+            "<" => (None, RedirectOp::Input),
+            "2>" => (Some(2), RedirectOp::Output),
+            _ => (Some(2), RedirectOp::Append),
+        };
+        Ok((
+            Redirect {
+                fd,
+                op,
+                target: self.compile_expr(target)?,
+            },
+            inner,
+        ))
+    }
+
+    fn compile_call(&mut self, form: &Form, name: &str, args: &[Form], context: Context) -> Result<Word, ShispError> {
+        // As for atoms, a variable hides the function of the same name.
+        if let Some(var) = self.env.lookup(name) {
+            return self.compile_funcall(Word::var(&var.clone()), args, context);
+        }
+        if self.recursion.as_ref().is_some_and(|r| r.name == name) {
+            self.warnings.push(ShispWarning::at(
+                ShispWarningType::UnconvertedRecursion(name.to_string()),
+                form.row,
+                form.col,
+            ));
+        }
+        let command = self.call_command(form, name, args)?;
+        self.emit_call(command, context);
+
+        let result = self.temp();
+        self.emit(Command::assign(&result, Word::var("shisp_r")));
+        Ok(Word::var(&result))
+    }
+
+    /// Emits the `command` calling a function. In a test, a failing status is ignored by running
+    /// it as the start of an `||` list, as sh runs the functions called in a condition.
+    fn emit_call(&mut self, command: Command, context: Context) {
+        if !context.tested {
+            return self.emit(command);
+        }
+        self.emit(Command::AndOr {
+            first: Box::new(command),
+            rest: vec![(AndOrOp::Or, Command::simple(vec![Word::raw(":")]))],
+        });
+    }
+
+    /// The command calling the Shisp function `name`, leaving its value in `shisp_r`.
+    fn call_command(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<Command, ShispError> {
+        let (function, args) = self.call_args(form, name, args)?;
+        let mut words = vec![Word::literal(&function.name)];
+        words.extend(args);
+        Ok(Command::simple(words))
+    }
+
+    /// The Shisp function `name` and the values of `args`, checked against its arity.
+    fn call_args(&mut self, form: &Form, name: &str, args: &[Form]) -> Result<(Function, Vec<Word>), ShispError> {
+        let function = self.env
+            .lookup_function(name)
+            .cloned()
+            .ok_or_else(|| form.error(ShispErrorType::UnboundFunction(name.to_string())))?;
+        if function.arity != args.len() {
+            return Err(form.error(ShispErrorType::ArityMismatch(
+                name.to_string(),
+                function.arity,
+                args.len(),
+            )));
+        }
+
+        let words = args
+            .iter()
+            .map(|arg| self.compile_expr(arg))
+            .collect::<Result<Vec<Word>, ShispError>>()?;
+        Ok((function, words))
+    }
+}
+
+fn define_parts(form: &Form) -> Result<(&str, &Form), ShispError> {
+    match form.as_list() {
+        Some([_, name, value]) if name.as_atom().is_some() => Ok((name.as_atom().unwrap(), value)),
+        _ => Err(form.error(ShispErrorType::MalformedForm(
+            "define expects `(define name value)`".to_string(),
+        ))),
+    }
+}
+
+/// The first of `EOF`, `EOF2`, `EOF3`... that is not a line of `body`, to end its here-document.
+fn heredoc_delimiter(body: &str) -> String {
+    (1..)
+        .map(|n| if n == 1 { "EOF".to_string() } else { format!("EOF{}", n) })
+        .find(|delimiter| !body.lines().any(|line| line == delimiter))
+        .unwrap()
+}
+
+/// Whether a `match` pattern matches every value.
+fn irrefutable(pattern: &Form) -> bool {
+    pattern.as_atom().is_some_and(|name| name != ".")
+}
+
+/// The pattern of a `case` clause, written as a string or a number.
+fn case_pattern(form: &Form) -> Result<Pattern, ShispError> {
+    match &form.form_type {
+        FormType::Str(text) => Ok(Pattern::parse(text)),
+        FormType::Number(n) => Ok(Pattern::parse(&n.to_string())),
+        _ => Err(form.error(ShispErrorType::MalformedForm(
+            "case patterns are strings, lists of strings or `else`".to_string(),
+        ))),
+    }
+}
+
+/// The functions `(defstruct name fields...)` defines, with their arities and what their bodies
+/// call: the constructor, then the accessor and setter of each field, then the predicate.
+fn struct_functions(form: &Form) -> Result<Vec<(String, usize, Command)>, ShispError> {
+    let malformed = |message: String| form.error(ShispErrorType::MalformedForm(message));
+    let (name, fields) = match form.as_list() {
+        Some([_, name, fields @ ..]) if name.as_atom().is_some() => (name.as_atom().unwrap(), fields),
+        _ => return Err(malformed("defstruct expects `(defstruct name fields...)`".to_string())),
+    };
+    let mut names: Vec<&str> = vec![];
+    for field in fields {
+        match field.as_atom() {
+            Some(field) if !names.contains(&field) => names.push(field),
+            Some(field) => return Err(malformed(format!("`{}` is declared twice", field))),
+            None => return Err(malformed("defstruct fields must be names".to_string())),
+        }
+    }
+
+    let record = || vec![Word::literal(name), Word::var("1")];
+    let mut functions = vec![(
+        format!("make-{}", name),
+        names.len(),
+        Command::call("shisp_new", vec![Word::literal(name), Word::raw("\"$@\"")]),
+    )];
+    for (i, field) in names.iter().enumerate() {
+        let index = Word::literal(&(i + 1).to_string());
+        functions.push((
+            format!("{}-{}", name, field),
+            1,
+            Command::call("shisp_field", [record(), vec![index.clone()]].concat()),
+        ));
+        functions.push((
+            format!("set-{}-{}!", name, field),
+            2,
+            Command::call("shisp_set_field", [record(), vec![index, Word::var("2")]].concat()),
+        ));
+    }
+    functions.push((format!("{}?", name), 1, Command::call("shisp_is", record())));
+    Ok(functions)
+}
+
+/// The parts of `(form (var value) body...)`, as taken by `dotimes` and `for-each`.
+fn loop_parts<'a>(form: &Form, args: &'a [Form]) -> Result<(&'a str, &'a Form, &'a [Form]), ShispError> {
+    match args {
+        [binding, body @ ..] => match binding.as_list() {
+            Some([name, value]) if name.as_atom().is_some() => Ok((name.as_atom().unwrap(), value, body)),
+            _ => Err(binding.error(ShispErrorType::MalformedForm(format!(
+                "{} expects its variable as `(name value)`",
+                form.head().unwrap_or_default()
+            )))),
+        },
+        _ => Err(form.error(ShispErrorType::MalformedForm(format!(
+            "{} expects `({} (name value) body...)`",
+            form.head().unwrap_or_default(),
+            form.head().unwrap_or_default()
+        )))),
+    }
+}
+
+/// The name of an environment variable, given as an atom or a string. Names the compiler uses for
+/// Shisp variables and its runtime are refused so the two can never be mixed up.
+fn environment_name(form: &Form) -> Result<String, ShispError> {
+    let name = match &form.form_type {
+        FormType::Atom(name) | FormType::Str(name) => name,
+        _ => return Err(form.error(ShispErrorType::MalformedForm(
+            "environment variables are named by an atom or a string".to_string(),
+        ))),
+    };
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(form.error(ShispErrorType::MalformedForm(format!(
+            "`{}` is not a valid environment variable name",
+            name
+        ))));
+    }
+    if name.starts_with("s_") || name.starts_with("shisp_") {
+        return Err(form.error(ShispErrorType::MalformedForm(format!(
+            "`{}` is reserved for the variables of the compiled program",
+            name
+        ))));
+    }
+    Ok(name.clone())
+}
+
+/// The name the script was run as, for messages.
+fn program_name() -> Word {
+    Word(vec![WordPart::Param("0##*/".to_string())])
+}
+
+/// `[ "$#" op n ]`, a test on the number of arguments left.
+fn argument_count(op: &str, n: &str) -> Command {
+    Command::simple(vec![
+        Word::raw("["),
+        Word::raw("\"$#\""),
+        Word::literal(op),
+        Word::literal(n),
+        Word::raw("]"),
+    ])
+}
+
+/// Reports a command-line error on stderr, as the `printf` `format` taking the program name and
+/// `args`, and exits with status 2.
+fn cli_error(format: &str, args: Vec<Word>) -> Vec<Command> {
+    let mut words = vec![Word::literal(format), program_name()];
+    words.extend(args);
+    vec![
+        Command::call("printf", words).redirect(Redirect {
+            fd: None,
+            op: RedirectOp::DupOutput,
+            target: Word::literal("2"),
+        }),
+        Command::call("exit", vec![Word::literal("2")]),
+    ]
+}
+
+fn lambda_params(params: &Form) -> Result<Vec<&str>, ShispError> {
+    params
+        .as_list()
+        .and_then(|params| params.iter().map(|p| p.as_atom()).collect::<Option<Vec<&str>>>())
+        .ok_or_else(|| params.error(ShispErrorType::MalformedForm(
+            "lambda parameters must be a list of names".to_string(),
+        )))
+}
+
+/// Collects the names `form` may refer to as variables, including those a `shell-literal`
+/// interpolates. Quoted forms refer to nothing.
+fn referenced_names(form: &Form, names: &mut Vec<String>) {
+    match &form.form_type {
+        FormType::Atom(name) => names.push(name.clone()),
+        FormType::List(forms) if form.head() == Some("shell-literal") => {
+            for arg in &forms[1..] {
+                match &arg.form_type {
+                    FormType::Str(text) => {
+                        let _ = shell_literal::interpolate(text, |name| {
+                            names.push(name.to_string());
+                            Some(String::new())
+                        });
+                    }
+                    _ => referenced_names(arg, names),
+                }
+            }
+        }
+        FormType::List(forms) => forms.iter().for_each(|f| referenced_names(f, names)),
+        FormType::Unquote(inner) | FormType::UnquoteSplice(inner) => referenced_names(inner, names),
+        _ => {}
+    }
+}
+
+fn function_parts(form: &Form) -> Result<(&str, Vec<&str>, &[Form]), ShispError> {
+    let malformed = || form.error(ShispErrorType::MalformedForm(
+        format!("{} expects `({} name (args...) body...)`", form.head().unwrap(), form.head().unwrap()),
+    ));
+
+    match form.as_list() {
+        Some([_, name, params, body @ ..]) => {
+            let name = name.as_atom().ok_or_else(malformed)?;
+            let params = params
+                .as_list()
+                .ok_or_else(malformed)?
+                .iter()
+                .map(|p| p.as_atom().ok_or_else(malformed))
+                .collect::<Result<Vec<&str>, ShispError>>()?;
+            Ok((name, params, body))
+        }
+        _ => Err(malformed()),
+    }
+}
+
+/// Maps a character offset in the joined text of a `shell-literal` back to the source.
+///
+/// `parts` holds the offset each argument starts at. Escape sequences in strings are assumed to
+/// be the two-character ones for `"` and `\\`, which is exact unless `\n` or `\t` was spelled out.
+fn literal_position(parts: &[(usize, &Form)], offset: usize) -> ((usize, usize), (usize, usize)) {
+    let (start, form) = match parts.iter().rev().find(|(start, _)| *start <= offset) {
+        Some(part) => *part,
+        None => return ((0, 0), (0, 0)),
+    };
+
+    let (mut row, mut col) = (form.row.0, form.col.0);
+    if let FormType::Str(s) = &form.form_type {
+        col += 1;
+        for c in s.chars().take(offset - start) {
+            match c {
+                '\n' => {
+                    row += 1;
+                    col = 0;
+                }
+                '"' | '\\' => col += 2,
+                _ => col += 1,
+            }
+        }
+    } else {
+        col += offset - start;
+    }
+    ((row, row), (col, col))
+}
+
+fn positional(index: usize) -> Word {
+    if index < 10 {
+        Word::raw(&format!("${}", index))
+    } else {
+        Word::raw(&format!("${{{}}}", index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler::{compile, compile_with};
+
+    /// The script compiled from `input` without its runtime, keeping the unused functions.
+    fn body(input: &str) -> String {
+        let options = Options { keep_all: true, ..Options::default() };
+        compile_with(input.to_string(), &options).unwrap().script.replacen(RUNTIME, "", 1)
+    }
+
+    #[test]
+    fn cond_chains() {
+        let output = body("(define a 1) (define b #f) (cond (a \"x\") (b \"y\") (else \"z\"))");
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_a=1",
+            "s_b=''",
+            "if [ -n \"$s_a\" ]; then",
+            "    shisp_t1=x",
+            "elif [ -n \"$s_b\" ]; then",
+            "    shisp_t1=y",
+            "else",
+            "    shisp_t1=z",
+            "fi",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn cond_nests_tests_with_commands() {
+        let output = body("(defun f (x) x) (define a #f) (cond (a 1) ((f \"it's\") 2))");
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_f() {",
+            "    shisp_save s_x",
+            "    s_x=$1",
+            "    shisp_r=\"$s_x\"",
+            "    shisp_restore s_x",
+            "}",
+            "s_a=''",
+            "if [ -n \"$s_a\" ]; then",
+            "    shisp_t1=1",
+            "else",
+            "    s_f 'it'\\''s' || :",
+            "    shisp_t2=\"$shisp_r\"",
+            "    if [ -n \"$shisp_t2\" ]; then",
+            "        shisp_t1=2",
+            "    else",
+            "        shisp_t1=''",
+            "    fi",
+            "fi",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn shell_literal_syntax() {
+        let error = compile("(define x 1)\n(shell-literal \"echo hi;\"\n  \"fi\")".to_string()).unwrap_err();
+        assert_eq!(
+            error.error_type(),
+            &ShispErrorType::ShellSyntax("`fi` without a matching `if`".to_string())
+        );
+        assert_eq!(error.position(), ((2, 2), (3, 3)));
+
+        let error = compile("(shell-literal \"printf \\\"%s\\\" 'a\")".to_string()).unwrap_err();
+        assert_eq!(error.position(), ((0, 0), (30, 30)));
+    }
+
+    #[test]
+    fn shell_literal_bashisms() {
+        let output = compile("(shell-literal \"ls\" \"&> /dev/null\")".to_string()).unwrap();
+        assert_eq!(output.warnings, vec![ShispWarning::at(
+            ShispWarningType::Bashism("`&>` redirections are not POSIX".to_string()),
+            (0, 0),
+            (21, 21),
+        )]);
+    }
+
+    #[test]
+    fn target_features() {
+        let options = Options { target: Target::Bash, keep_all: true, ..Options::default() };
+        let output = compile_with("(defun f (x) (cond (x \"a\tb\") (else x)))".to_string(), &options).unwrap();
+        assert_eq!(output.script.replacen(RUNTIME, "", 1), [
+            "#!/usr/bin/env bash",
+            "s_f() {",
+            "    local s_x shisp_t1",
+            "    s_x=$1",
+            "    if [[ -n \"$s_x\" ]]; then",
+            "        shisp_t1=$'a\\tb'",
+            "    else",
+            "        shisp_t1=\"$s_x\"",
+            "    fi",
+            "    shisp_r=\"$shisp_t1\"",
+            "}",
+            "",
+        ].join("\n"));
+        assert_eq!(output.features, vec![Feature::Local, Feature::DoubleBracketTests, Feature::DollarSingleQuotes]);
+
+        let options = Options { target: Target::Dash, ..Options::default() };
+        let output = compile_with("(shell-literal \"[[ -n x ]] && local y\")".to_string(), &options).unwrap();
+        assert_eq!(output.features, vec![Feature::Local, Feature::DoubleBracketTests]);
+        assert_eq!(output.warnings, vec![ShispWarning::at(
+            ShispWarningType::Unsupported(Feature::DoubleBracketTests, Target::Dash),
+            (0, 0),
+            (16, 16),
+        )]);
+    }
+
+    #[test]
+    fn source_map() {
+        let input = "(define a 1)\n(defun f ()\n  (shell-literal \"echo ,a\"))";
+        let output = compile_with(input.to_string(), &Options { keep_all: true, ..Options::default() }).unwrap();
+        let line = |text: &str| output.script.lines().position(|l| l.trim() == text).unwrap() + 1;
+        assert_eq!(output.source_map.lookup(1), None);
+        assert_eq!(output.source_map.lookup(line("s_a=1")), Some((0, 0)));
+        assert_eq!(output.source_map.lookup(line("s_f() {")), Some((1, 0)));
+        assert_eq!(output.source_map.lookup(line("echo \"${s_a}\"")), Some((2, 2)));
+        assert_eq!(output.source_map.lookup(line("shisp_r=''")), Some((1, 0)));
+    }
+
+    #[test]
+    fn comments() {
+        let input =
+            ";; The greeting\n(define a \"hi\")\n(defun f ()\n  ;; Prints it\n  ;;\n  (shell-literal \"echo ,a\"))";
+        assert_eq!(body(input), [
+            "#!/bin/sh",
+            "# The greeting",
+            "s_a=hi",
+            "s_f() {",
+            "    # Prints it",
+            "    #",
+            "    echo \"${s_a}\"",
+            "    shisp_r=''",
+            "}",
+            "",
+        ].join("\n"));
+
+        let options = Options { strip_comments: true, ..Options::default() };
+        let output = compile_with(input.to_string(), &options).unwrap();
+        assert!(!output.script.contains("# The greeting"));
+    }
+
+    #[test]
+    fn pipes_and_redirections() {
+        let output = body(r#"
+            (define file "out file")
+            (cond ((2> "/dev/null" (< file (pipe (grep "x") (wc "-l")))) 1))
+            (> file (2>&1 (pipe (ls))))
+        "#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_file='out file'",
+            "{",
+            "    grep x | wc -l",
+            "} 2> /dev/null < \"$s_file\"",
+            "shisp_t2=$?",
+            "if [ \"$shisp_t2\" -eq 0 ]; then",
+            "    shisp_t1=1",
+            "else",
+            "    shisp_t1=''",
+            "fi",
+            "ls > \"$s_file\" 2>&1",
+            "",
+        ].join("\n"));
+
+        let error = compile("(pipe \"ls\")".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+    }
+
+    #[test]
+    fn external_commands() {
+        let output = body(r#"
+            (define dir (capture "pwd"))
+            (cond ((run "test" "-d" dir) (status (pipe (ls dir) (run "wc" "-l")))))
+        "#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "shisp_t1=\"$(pwd)\"",
+            "s_dir=\"$shisp_t1\"",
+            "if test -d \"$s_dir\"; then",
+            "    if ls \"$s_dir\" | wc -l; then",
+            "        shisp_t3=0",
+            "    else",
+            "        shisp_t3=$?",
+            "    fi",
+            "    shisp_t2=\"$shisp_t3\"",
+            "else",
+            "    shisp_t2=''",
+            "fi",
+            "",
+        ].join("\n"));
+
+        let error = compile("(run)".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+    }
+
+    #[test]
+    fn case_clauses() {
+        let input = r#"(define f "x") (case f ("*.tar.gz" 1) (("a b" "[ab]$") 2) ("x.tar.gz" 3))"#;
+        let output = compile(input.to_string()).unwrap();
+        assert!(output.script.ends_with(&[
+            "case \"$s_f\" in",
+            "    *.tar.gz)",
+            "        shisp_t1=1",
+            "        ;;",
+            "    'a b'|[ab]'$')",
+            "        shisp_t1=2",
+            "        ;;",
+            "    x.tar.gz)",
+            "        shisp_t1=3",
+            "        ;;",
+            "    *)",
+            "        shisp_t1=''",
+            "        ;;",
+            "esac",
+            "",
+        ].join("\n")));
+        let warning = ShispWarning::at(ShispWarningType::UnreachableClause, (0, 0), (58, 58));
+        assert_eq!(output.warnings, vec![warning]);
+
+        let output = compile("(case 1 (else 1) (\"2\" 2))".to_string()).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        let output = compile("(case 1 ((\"1\" \"2\") 1) (\"2\" 2) ((\"2\" \"3\") 3))".to_string()).unwrap();
+        assert_eq!(output.warnings.len(), 1);
+        let error = compile("(case 1 (x 1))".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+    }
+
+    #[test]
+    fn match_clauses() {
+        let output = compile("(define l '()) (match l ((x . _) x) (() 0))".to_string()).unwrap();
+        assert!(output.script.ends_with(&[
+            "s_l=''",
+            "shisp_t1=\"$s_l\"",
+            "shisp_t2=''",
+            "shisp_t3=''",
+            "case \"$shisp_t1\" in",
+            "    shisp_c[0-9]*)",
+            "        shisp_car \"$shisp_t1\"",
+            "        shisp_t4=\"$shisp_r\"",
+            "        shisp_cdr \"$shisp_t1\"",
+            "        shisp_t5=\"$shisp_r\"",
+            "        s_x=\"$shisp_t4\"",
+            "        shisp_t3='#t'",
+            "        shisp_t2=\"$s_x\"",
+            "        ;;",
+            "esac",
+            "if [ -z \"$shisp_t3\" ]; then",
+            "    if [ -z \"$shisp_t1\" ]; then",
+            "        shisp_t2=0",
+            "    fi",
+            "fi",
+            "",
+        ].join("\n")));
+        let warning = ShispWarning::at(ShispWarningType::NonExhaustiveMatch, (0, 0), (15, 15));
+        assert_eq!(output.warnings, vec![warning]);
+
+        let output = compile("(match 1 (\"1\" 1) (_ 2))".to_string()).unwrap();
+        assert!(output.warnings.is_empty());
+        for input in ["(match 1 (. 1))", "(match 1 ((a . b c) 1))", "(match 1 ((. a) 1))", "(match 1 x)"] {
+            let error = compile(input.to_string()).unwrap_err();
+            assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn structs() {
+        let output = body("(defstruct pt x) (pt-x (make-pt 1))");
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_make_2dpt() {",
+            "    shisp_new pt \"$@\"",
+            "}",
+            "s_pt_2dx() {",
+            "    shisp_field pt \"$1\" 1",
+            "}",
+            "s_set_2dpt_2dx_21() {",
+            "    shisp_set_field pt \"$1\" 1 \"$2\"",
+            "}",
+            "s_pt_3f() {",
+            "    shisp_is pt \"$1\"",
+            "}",
+            "s_make_2dpt 1",
+            "shisp_t1=\"$shisp_r\"",
+            "s_pt_2dx \"$shisp_t1\"",
+            "shisp_t2=\"$shisp_r\"",
+            "",
+        ].join("\n"));
+
+        for input in ["(defstruct)", "(defstruct pt x x)", "(defstruct pt (x 1))", "(defstruct pt x) (make-pt)"] {
+            assert!(compile(input.to_string()).is_err(), "{}", input);
+        }
+    }
+
+    #[test]
+    fn maps() {
+        let output = body("(define m (make-map)) (map-put! m \"k\" 1)");
+        assert_eq!(output, format!("#!/bin/sh\n{}{}", MAPS, [
+            "shisp_map_new",
+            "shisp_t1=\"$shisp_r\"",
+            "s_m=\"$shisp_t1\"",
+            "shisp_map_put \"$s_m\" k 1",
+            "shisp_t2=\"$shisp_r\"",
+            "",
+        ].join("\n")));
+        assert!(!compile("(define l (list 1))".to_string()).unwrap().script.contains("shisp_map_new"));
+
+        let bash = Options { target: Target::Bash, keep_all: true, ..Options::default() };
+        let output = compile_with("(map-keys (make-map))".to_string(), &bash).unwrap();
+        assert!(output.script.contains(ASSOCIATIVE_MAPS));
+        assert_eq!(output.features, vec![Feature::AssociativeArrays]);
+
+        let error = compile("(map-get (make-map))".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("map-get".to_string(), 2, 1));
+    }
+
+    #[test]
+    fn output_forms() {
+        let output = body(r#"(define n 1) (eprintln (format "~a%~%" n) n) (print (format "-~d" 2))"#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_n=1",
+            "shisp_t1=\"$(printf '%s%%\\n' \"$s_n\"; printf .)\"",
+            "shisp_t1=\"${shisp_t1%.}\"",
+            "printf '%s %s\\n' \"$shisp_t1\" \"$s_n\" >&2",
+            "shisp_t2=\"$(printf -- -%d 2)\"",
+            "printf %s \"$shisp_t2\"",
+            "",
+        ].join("\n"));
+
+        let error = compile("(format \"~a and ~s\" 1)".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("format \"~a and ~s\"".to_string(), 2, 1));
+        for input in ["(format \"~y\")", "(format x)", "(format)"] {
+            let error = compile(input.to_string()).unwrap_err();
+            assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn heredocs() {
+        let output = body(
+            "(define v 1) (heredoc \"cat\" \">> out\" \"a\\nEOF\") (heredoc-interpolate \"tr\" \"a\" \"b\" \",v $v\")",
+        );
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_v=1",
+            "cat >> out <<'EOF2'",
+            "a",
+            "EOF",
+            "EOF2",
+            "tr a b <<EOF",
+            "${s_v} \\$v",
+            "EOF",
+            "",
+        ].join("\n"));
+
+        let inputs = [
+            "(heredoc \"text\")",
+            "(heredoc \"cat\" x)",
+            "(heredoc \"cat\" \">\" \"text\")",
+            "(heredoc \">f\" \"text\")",
+        ];
+        for input in inputs {
+            let error = compile(input.to_string()).unwrap_err();
+            assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)), "{}", input);
+        }
+        let error = compile("(heredoc-interpolate \"cat\" \",nope\")".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::UnboundVariable("nope".to_string()));
+    }
+
+    #[test]
+    fn file_tests() {
+        let output = body("(define p \"f\") (cond ((directory? p) 1)) (define x (executable? p)) (path-join p \"b\")");
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_p=f",
+            "if [ -d \"$s_p\" ]; then",
+            "    shisp_t1=1",
+            "else",
+            "    shisp_t1=''",
+            "fi",
+            "if [ -x \"$s_p\" ]; then",
+            "    shisp_t2='#t'",
+            "else",
+            "    shisp_t2=''",
+            "fi",
+            "s_x=\"$shisp_t2\"",
+            "shisp_path_join \"$s_p\" b",
+            "shisp_t3=\"$shisp_r\"",
+            "",
+        ].join("\n"));
+
+        let error = compile("(file? \"a\" \"b\")".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("file?".to_string(), 1, 2));
+    }
+
+    #[test]
+    fn strings() {
+        let output = body(
+            "(define s \"a b\") (string-length \"xy\") (string-prefix? s \"a*\") (string-upcase s) (substring s 1)",
+        );
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_s='a b'",
+            "shisp_t1=xy",
+            "shisp_t2=\"${#shisp_t1}\"",
+            "case \"$s_s\" in",
+            "    'a*'*)",
+            "        shisp_t3='#t'",
+            "        ;;",
+            "    *)",
+            "        shisp_t3=''",
+            "        ;;",
+            "esac",
+            "shisp_t4=\"$(printf %s. \"$s_s\" | tr '[:lower:]' '[:upper:]')\"",
+            "shisp_t4=\"${shisp_t4%.}\"",
+            "shisp_substring \"$s_s\" 1 ''",
+            "shisp_t5=\"$shisp_r\"",
+            "",
+        ].join("\n"));
+
+        let bash = Options { target: Target::Bash, ..Options::default() };
+        let output = compile_with("(define s \"a\") (string-downcase s)".to_string(), &bash).unwrap();
+        assert!(output.script.ends_with("shisp_t1=\"${s_s,,}\"\n"));
+        assert_eq!(output.features, vec![Feature::CaseModifications]);
+
+        let error = compile("(substring \"a\")".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("substring".to_string(), 3, 1));
+    }
+
+    #[test]
+    fn loops() {
+        let output = body(r#"
+            (dotimes (i 2) (for-each (x '(a)) x))
+            (for-each-line (cat "f") (line) line)
+        "#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "shisp_t1=2",
+            "s_i=0",
+            "while [ \"$s_i\" -lt \"$shisp_t1\" ]; do",
+            "    shisp_cons a ''",
+            "    shisp_t2=\"$shisp_r\"",
+            "    shisp_t3=\"$shisp_t2\"",
+            "    while [ -n \"$shisp_t3\" ]; do",
+            "        shisp_car \"$shisp_t3\"",
+            "        s_x=\"$shisp_r\"",
+            "        shisp_cdr \"$shisp_t3\"",
+            "        shisp_t3=\"$shisp_r\"",
+            "    done",
+            "    s_i=$((s_i + 1))",
+            "done",
+            "cat f | while IFS='' read -r s_line || [ -n \"$s_line\" ]; do",
+            "    :",
+            "done",
+            "",
+        ].join("\n"));
+
+        let error = compile("(dotimes i 3)".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+        let error = compile("(for-each-line (cat \"f\") line)".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+    }
+
+    #[test]
+    fn tail_calls() {
+        let keep_all = Options { keep_all: true, ..Options::default() };
+        let output = compile_with(r#"
+            (defun last (l)
+              (let ((rest (cdr l)))
+                (cond (rest (last rest))
+                      (else (car l)))))
+        "#.to_string(), &keep_all).unwrap();
+        assert!(output.warnings.is_empty());
+        assert!(output.script.ends_with(&[
+            "s_last() {",
+            "    shisp_save s_l shisp_t1 s_rest shisp_t2 shisp_t3",
+            "    s_l=$1",
+            "    while :; do",
+            "        shisp_cdr \"$s_l\"",
+            "        shisp_t1=\"$shisp_r\"",
+            "        s_rest=\"$shisp_t1\"",
+            "        if [ -n \"$s_rest\" ]; then",
+            "            s_l=\"$s_rest\"",
+            "            continue",
+            "        else",
+            "            shisp_car \"$s_l\"",
+            "            shisp_t3=\"$shisp_r\"",
+            "            shisp_t2=\"$shisp_t3\"",
+            "        fi",
+            "        shisp_r=\"$shisp_t2\"",
+            "        break",
+            "    done",
+            "    shisp_restore s_l shisp_t1 s_rest shisp_t2 shisp_t3",
+            "}",
+            "",
+        ].join("\n")));
+
+        let input = "(defun f (n) (cond (n (cons n (f n))) (else (f (car n)))))";
+        let output = compile_with(input.to_string(), &keep_all).unwrap();
+        let warning = ShispWarning::at(ShispWarningType::UnconvertedRecursion("f".to_string()), (0, 0), (30, 30));
+        assert_eq!(output.warnings, vec![warning.clone()]);
+        assert!(warning.is_note());
+        assert!(output.script.contains("continue"));
+    }
+
+    #[test]
+    fn unused_functions() {
+        let input = "(defun used () (car '(1)))\n(defun unused () (lambda (x) (string-upcase x)))\n(used)";
+        let bash = Options { target: Target::Bash, ..Options::default() };
+        let output = compile_with(input.to_string(), &bash).unwrap();
+        assert!(output.script.contains("s_used() {") && !output.script.contains("s_unused"));
+        assert!(!output.script.contains("shisp_lambda1"));
+        assert!(output.script.contains("shisp_car() {") && !output.script.contains("shisp_cdr() {"));
+        assert!(output.removed.contains(&"shisp_cdr".to_string()));
+        assert!(!output.features.contains(&Feature::CaseModifications));
+        let warning = ShispWarning::at(ShispWarningType::UnusedFunction("unused".to_string()), (1, 1), (0, 0));
+        assert_eq!(output.warnings, vec![warning.clone()]);
+        assert!(warning.is_note());
+
+        let output = compile_with(input.to_string(), &Options { keep_all: true, ..bash }).unwrap();
+        assert!(output.script.contains("s_unused() {") && output.script.contains(RUNTIME));
+        assert!(output.warnings.is_empty() && output.removed.is_empty());
+
+        let error = compile("(defun unused () (nope))".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::UnboundFunction("nope".to_string()));
+    }
+
+    #[test]
+    fn lambdas() {
+        let output = body("(defun adder (n) (lambda (x) (list n x))) (funcall (adder 1) 2)");
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "shisp_lambda1() {",
+            "    shisp_save shisp_t1 s_n__2 s_x shisp_t2 shisp_t3",
+            "    shisp_t1=$1",
+            "    shisp_car \"$shisp_t1\"",
+            "    s_n__2=\"$shisp_r\"",
+            "    s_x=$2",
+            "    shisp_cons \"$s_x\" ''",
+            "    shisp_t2=\"$shisp_r\"",
+            "    shisp_cons \"$s_n__2\" \"$shisp_t2\"",
+            "    shisp_t3=\"$shisp_r\"",
+            "    shisp_r=\"$shisp_t3\"",
+            "    shisp_restore shisp_t1 s_n__2 s_x shisp_t2 shisp_t3",
+            "}",
+            "s_adder() {",
+            "    shisp_save s_n shisp_t4 shisp_t5",
+            "    s_n=$1",
+            "    shisp_cons \"$s_n\" ''",
+            "    shisp_t4=\"$shisp_r\"",
+            "    shisp_cons shisp_lambda1 \"$shisp_t4\"",
+            "    shisp_t5=\"$shisp_r\"",
+            "    shisp_r=\"$shisp_t5\"",
+            "    shisp_restore s_n shisp_t4 shisp_t5",
+            "}",
+            "s_adder 1",
+            "shisp_t6=\"$shisp_r\"",
+            "shisp_funcall \"$shisp_t6\" 2",
+            "shisp_t7=\"$shisp_r\"",
+            "",
+        ].join("\n"));
+
+        let output = body("(define f (lambda () car))");
+        assert!(output.ends_with("s_f=shisp_lambda1\n"));
+        assert!(output.contains("    shisp_r=shisp_car\n"));
+    }
+
+    #[test]
+    fn environment_variables() {
+        let output = body(r#"
+            (define home (getenv HOME))
+            (setenv! "s" home)
+            (export! PATH)
+        "#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "s_home=\"${HOME-}\"",
+            "s=\"$s_home\"",
+            "export s",
+            "export PATH",
+            "",
+        ].join("\n"));
+
+        for input in ["(getenv \"s_home\")", "(getenv \"shisp_r\")", "(export! \"A-B\")", "(setenv! X)"] {
+            let error = compile(input.to_string()).unwrap_err();
+            assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)), "{}", input);
+        }
+    }
+
+    #[test]
+    fn error_handling() {
+        let input = r#"
+            (unwind-protect (run "make") (run "rm" "-f" "tmp"))
+            (try (run "false") (catch status status))
+        "#;
+        let options = Options { strict: true, keep_all: true, ..Options::default() };
+        let output = compile_with(input.to_string(), &options).unwrap();
+        let script = output.script.replacen(RUNTIME, "", 1);
+        assert_eq!(script, [
+            "#!/bin/sh",
+            "set -eu",
+            "shisp_cleanup1() {",
+            "    rm -f tmp",
+            "}",
+            "shisp_protect shisp_cleanup1",
+            "make",
+            "shisp_unprotect",
+            "set +e",
+            "(",
+            "    shisp_unwind=''",
+            "    set -e",
+            "    false",
+            ")",
+            "shisp_t1=$?",
+            "set -e",
+            "if [ \"$shisp_t1\" -ne 0 ]; then",
+            "    s_status=\"$shisp_t1\"",
+            "    shisp_t2=\"$s_status\"",
+            "else",
+            "    shisp_t2='#t'",
+            "fi",
+            "",
+        ].join("\n"));
+
+        // Without --strict, only the body of a try runs with `set -e`.
+        let script = body(input);
+        assert!(!script.contains("set +e") && !script.contains("set -eu"));
+
+        let error = compile("(try (catch s s))".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+        let error = compile("(try (run \"a\") (catch))".to_string()).unwrap_err();
+        assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)));
+
+        // A function whose value is that of a command returns its exit status.
+        assert_eq!(body("(defun f () (run \"deploy\"))"), [
+            "#!/bin/sh",
+            "s_f() {",
+            "    shisp_save shisp_t1 shisp_t2",
+            "    shisp_t1=0",
+            "    if deploy; then",
+            "        shisp_t2='#t'",
+            "    else",
+            "        shisp_t1=$?",
+            "        shisp_t2=''",
+            "    fi",
+            "    shisp_r=\"$shisp_t2\"",
+            "    shisp_status=\"$shisp_t1\"",
+            "    shisp_restore shisp_t1 shisp_t2",
+            "    return \"$shisp_status\"",
+            "}",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn background_jobs() {
+        let output = body(r#"
+            (define job (spawn (pipe (cat "a") (sort))))
+            (wait job)
+            (parallel (run "make" "a") (run "make" "b"))
+        "#);
+        assert_eq!(output, [
+            "#!/bin/sh",
+            "cat a | sort &",
+            "shisp_t1=$!",
+            "s_job=\"$shisp_t1\"",
+            "if wait \"$s_job\"; then",
+            "    shisp_t2=0",
+            "else",
+            "    shisp_t2=$?",
+            "fi",
+            "make a &",
+            "shisp_t3=$!",
+            "make b &",
+            "shisp_t4=$!",
+            "shisp_t5=0",
+            "wait \"$shisp_t3\" || shisp_t5=$?",
+            "wait \"$shisp_t4\" || shisp_t5=$?",
+            "shisp_return \"$shisp_t5\"",
+            "",
+        ].join("\n"));
+
+        let output = body("(spawn (unwind-protect (run \"a\") (run \"b\")))");
+        assert!(output.contains("{ shisp_unwind=''; shisp_protect shisp_cleanup1; a; shisp_unprotect; } &"));
+    }
+
+    #[test]
+    fn function_arity() {
+        let error = compile("(defun f (a b) a) (f 1)".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("f".to_string(), 2, 1));
+        let error = compile("(g 1)".to_string()).unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::UnboundFunction("g".to_string()));
+    }
+}
@@ -0,0 +1,168 @@
+use std::collections::HashMap;
+
+/// Prefix of every sh name generated from a Shisp identifier.
+const PREFIX: &str = "s_";
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Function {
+    pub name: String,
+    pub arity: usize,
+    pub pure: bool,
+}
+
+/// Tracks the Shisp bindings visible at a point in the program and the sh names they compile to.
+///
+/// Every binding gets a name that is unique in the whole program, so shadowing never needs any
+/// runtime support; only recursion does, which is handled by the function prologue.
+#[derive(Debug)]
+pub struct Environment {
+    frames: Vec<HashMap<String, String>>,
+    functions: HashMap<String, Function>,
+    bound_names: HashMap<String, usize>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment {
+            frames: vec![HashMap::new()],
+            functions: HashMap::new(),
+            bound_names: HashMap::new(),
+        }
+    }
+
+    pub fn push_frame(&mut self) {
+        self.frames.push(HashMap::new());
+    }
+
+    pub fn pop_frame(&mut self) {
+        if self.frames.len() > 1 {
+            self.frames.pop();
+        }
+    }
+
+    pub fn is_global(&self) -> bool {
+        self.frames.len() == 1
+    }
+
+    /// Binds `name` in the innermost frame to a fresh sh variable and returns it.
+    pub fn bind(&mut self, name: &str) -> String {
+        let sh_name = self.fresh_name(name);
+        self.frames
+            .last_mut()
+            .unwrap()
+            .insert(name.to_string(), sh_name.clone());
+        sh_name
+    }
+
+    /// Binds `name` in the global frame, reusing the existing variable if it is already defined.
+    pub fn bind_global(&mut self, name: &str) -> String {
+        if let Some(sh_name) = self.frames[0].get(name) {
+            return sh_name.clone();
+        }
+
+        let sh_name = self.fresh_name(name);
+        self.frames[0].insert(name.to_string(), sh_name.clone());
+        sh_name
+    }
+
+    fn fresh_name(&mut self, name: &str) -> String {
+        let count = self.bound_names.entry(name.to_string()).or_insert(0);
+        *count += 1;
+        if *count == 1 {
+            mangle(name)
+        } else {
+            format!("{}__{}", mangle(name), count)
+        }
+    }
+
+    pub fn lookup(&self, name: &str) -> Option<&String> {
+        self.frames.iter().rev().find_map(|frame| frame.get(name))
+    }
+
+    /// Looks `name` up in every frame but the global one.
+    pub fn lookup_local(&self, name: &str) -> Option<&String> {
+        self.frames[1..].iter().rev().find_map(|frame| frame.get(name))
+    }
+
+    pub fn define_function(&mut self, name: &str, arity: usize, pure: bool) -> &Function {
+        self.functions.insert(name.to_string(), Function {
+            name: mangle(name),
+            arity,
+            pure,
+        });
+        &self.functions[name]
+    }
+
+    /// Defines `name` as a function of the runtime, called as `sh_name`.
+    pub fn define_builtin(&mut self, name: &str, sh_name: &str, arity: usize) {
+        self.functions.insert(name.to_string(), Function {
+            name: sh_name.to_string(),
+            arity,
+            pure: true,
+        });
+    }
+
+    pub fn lookup_function(&self, name: &str) -> Option<&Function> {
+        self.functions.get(name)
+    }
+}
+
+/// Turns a Shisp identifier into a valid sh name that cannot collide with environment variables,
+/// the runtime or any other identifier.
+///
+/// ASCII letters and digits are kept; every other character becomes `_` followed by two hex
+/// digits (or `_U` and eight for characters outside Latin-1), so the `__N` suffix `bind` adds to
+/// shadowing bindings can never collide with another mangled name.
+pub fn mangle(name: &str) -> String {
+    let mut out = String::from(PREFIX);
+    for c in name.chars() {
+        match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => out.push(c),
+            c if (c as u32) < 0x100 => out.push_str(&format!("_{:02x}", c as u32)),
+            c => out.push_str(&format!("_U{:08x}", c as u32)),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mangle() {
+        assert_eq!(mangle("x"), "s_x");
+        assert_eq!(mangle("my-var"), "s_my_2dvar");
+        assert_eq!(mangle("my_var"), "s_my_5fvar");
+        assert_eq!(mangle("empty?"), "s_empty_3f");
+        assert_eq!(mangle("λ"), "s__U000003bb");
+    }
+
+    #[test]
+    fn shadowing() {
+        let mut env = Environment::new();
+        assert_eq!(env.bind_global("x"), "s_x");
+
+        env.push_frame();
+        assert_eq!(env.bind("x"), "s_x__2");
+        assert_eq!(env.lookup("x"), Some(&"s_x__2".to_string()));
+        env.pop_frame();
+
+        assert_eq!(env.lookup("x"), Some(&"s_x".to_string()));
+        assert_eq!(env.lookup("y"), None);
+        assert_eq!(env.bind_global("x"), "s_x");
+    }
+
+    #[test]
+    fn functions() {
+        let mut env = Environment::new();
+        env.define_function("add-one", 1, false);
+
+        assert_eq!(env.lookup_function("add-one"), Some(&Function {
+            name: "s_add_2done".to_string(),
+            arity: 1,
+            pure: false,
+        }));
+        assert_eq!(env.lookup_function("add-two"), None);
+    }
+}
@@ -0,0 +1,84 @@
+//! The directives of `format` strings, and the `printf` formats they are compiled to.
+
+/// A `format` string translated for `printf`.
+#[derive(Debug, PartialEq)]
+pub struct Format {
+    pub printf: String,
+    /// How many arguments the directives take.
+    pub arguments: usize,
+    /// Whether the output can end in a newline, which a command substitution would drop.
+    pub trailing_newline: bool,
+}
+
+/// Reads a `format` string, where `~a` is an argument as it is, `~s` an argument in double quotes,
+/// `~d` and `~x` an integer argument in decimal and hexadecimal, `~%` a newline and `~~` a tilde.
+/// The error describes the first directive that is not one of these.
+pub fn parse(text: &str) -> Result<Format, String> {
+    let mut printf = String::new();
+    let mut arguments = 0;
+    let mut trailing_newline = false;
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        trailing_newline = false;
+        let directive = match c {
+            '~' => chars.next(),
+            '%' => {
+                printf.push_str("%%");
+                continue;
+            }
+            '\\' => {
+                printf.push_str("\\\\");
+                continue;
+            }
+            c => {
+                trailing_newline = c == '\n';
+                printf.push(c);
+                continue;
+            }
+        };
+        let (conversion, takes_argument, newline) = match directive.map(|d| d.to_ascii_lowercase()) {
+            Some('a') => ("%s", true, true),
+            Some('s') => ("\"%s\"", true, false),
+            Some('d') => ("%d", true, false),
+            Some('x') => ("%x", true, false),
+            Some('%') => ("\\n", false, true),
+            Some('~') => ("~", false, false),
+            Some(d) => return Err(format!("unknown format directive `~{}`", d)),
+            None => return Err("the format string ends in the middle of a `~` directive".to_string()),
+        };
+        printf.push_str(conversion);
+        if takes_argument {
+            arguments += 1;
+        }
+        trailing_newline = newline;
+    }
+    Ok(Format {
+        printf,
+        arguments,
+        trailing_newline,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directives() {
+        assert_eq!(parse("~a items in ~S~%"), Ok(Format {
+            printf: "%s items in \"%s\"\\n".to_string(),
+            arguments: 2,
+            trailing_newline: true,
+        }));
+        assert_eq!(parse("100% ~~ \\ ~d ~x."), Ok(Format {
+            printf: "100%% ~ \\\\ %d %x.".to_string(),
+            arguments: 2,
+            trailing_newline: false,
+        }));
+        assert!(parse("~a").unwrap().trailing_newline);
+        assert!(!parse("~d").unwrap().trailing_newline);
+        assert!(parse("line\n").unwrap().trailing_newline);
+        assert_eq!(parse("~q"), Err("unknown format directive `~q`".to_string()));
+        assert!(parse("oops ~").is_err());
+    }
+}
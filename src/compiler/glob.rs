@@ -0,0 +1,248 @@
+//! The glob patterns of `case` clauses: their sh spelling, with the characters that are not
+//! wildcards quoted, and enough matching to tell when a clause can never be reached.
+
+use crate::sh_ast::{Word, WordPart};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Piece {
+    Char(char),
+    /// `*`
+    Any,
+    /// `?`
+    One,
+    /// A bracket expression such as `[a-z]` or `[![:digit:]_]`.
+    Class { negated: bool, members: Vec<Member> },
+}
+
+/// What a bracket expression lists: a character, a range such as `a-z`, or a named class such as
+/// `[:digit:]`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Member {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+/// The character classes of POSIX bracket expressions.
+const CLASSES: [&str; 12] =
+    ["alnum", "alpha", "blank", "cntrl", "digit", "graph", "lower", "print", "punct", "space", "upper", "xdigit"];
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Pattern(Vec<Piece>);
+
+impl Pattern {
+    /// Reads a pattern written as in sh: `*`, `?` and bracket expressions are wildcards, and a
+    /// backslash makes the character after it literal.
+    pub fn parse(text: &str) -> Pattern {
+        let chars = text.chars().collect::<Vec<char>>();
+        let mut pieces = vec![];
+        let mut i = 0;
+        while i < chars.len() {
+            match chars[i] {
+                '*' => pieces.push(Piece::Any),
+                '?' => pieces.push(Piece::One),
+                '\\' if i + 1 < chars.len() => {
+                    i += 1;
+                    pieces.push(Piece::Char(chars[i]));
+                }
+                '[' => match parse_class(&chars, i) {
+                    Some((class, end)) => {
+                        pieces.push(class);
+                        i = end;
+                    }
+                    None => pieces.push(Piece::Char('[')),
+                },
+                c => pieces.push(Piece::Char(c)),
+            }
+            i += 1;
+        }
+        Pattern(pieces)
+    }
+
+    /// The pattern as a `case` pattern word, with only its wildcards left unquoted.
+    pub fn to_word(&self) -> Word {
+        let mut parts: Vec<WordPart> = vec![];
+        for piece in &self.0 {
+            match piece {
+                Piece::Char(c) => match parts.last_mut() {
+                    Some(WordPart::Literal(text)) => text.push(*c),
+                    _ => parts.push(WordPart::Literal(c.to_string())),
+                },
+                Piece::Any => parts.push(WordPart::Raw("*".to_string())),
+                Piece::One => parts.push(WordPart::Raw("?".to_string())),
+                Piece::Class { negated, members } => {
+                    parts.push(WordPart::Raw(if *negated { "[!" } else { "[" }.to_string()));
+                    for member in members {
+                        match member {
+                            Member::Char(c) => parts.push(class_char(*c)),
+                            Member::Range(from, to) => {
+                                parts.push(class_char(*from));
+                                parts.push(WordPart::Raw("-".to_string()));
+                                parts.push(class_char(*to));
+                            }
+                            Member::Named(name) => parts.push(WordPart::Raw(format!("[:{}:]", name))),
+                        }
+                    }
+                    parts.push(WordPart::Raw("]".to_string()));
+                }
+            }
+        }
+        Word(parts)
+    }
+
+    /// The text the pattern matches, if it has no wildcards.
+    fn literal(&self) -> Option<String> {
+        self.0
+            .iter()
+            .map(|piece| match piece {
+                Piece::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether every string this pattern matches is also matched by `other`. Only the simple
+    /// cases are recognized: `other` matching everything, the same pattern, or a pattern without
+    /// wildcards that `other` matches.
+    pub fn covered_by(&self, other: &Pattern) -> bool {
+        other.0.iter().all(|piece| *piece == Piece::Any)
+            || self == other
+            || self.literal().is_some_and(|text| other.matches(&text.chars().collect::<Vec<char>>()))
+    }
+
+    fn matches(&self, text: &[char]) -> bool {
+        matches_from(&self.0, text)
+    }
+}
+
+fn matches_from(pieces: &[Piece], text: &[char]) -> bool {
+    match pieces.split_first() {
+        None => text.is_empty(),
+        Some((Piece::Any, rest)) => (0..=text.len()).any(|i| matches_from(rest, &text[i..])),
+        Some((piece, rest)) => match text.split_first() {
+            Some((c, text)) => piece_matches(piece, *c) && matches_from(rest, text),
+            None => false,
+        },
+    }
+}
+
+fn piece_matches(piece: &Piece, c: char) -> bool {
+    match piece {
+        Piece::Char(p) => *p == c,
+        Piece::Any | Piece::One => true,
+        Piece::Class { negated, members } => {
+            let found = members.iter().any(|member| match member {
+                Member::Char(p) => *p == c,
+                Member::Range(from, to) => *from <= c && c <= *to,
+                Member::Named(name) => match name.as_str() {
+                    "alnum" => c.is_alphanumeric(),
+                    "alpha" => c.is_alphabetic(),
+                    "blank" => c == ' ' || c == '\t',
+                    "cntrl" => c.is_control(),
+                    "digit" => c.is_ascii_digit(),
+                    "graph" => c.is_ascii_graphic(),
+                    "lower" => c.is_lowercase(),
+                    "print" => c.is_ascii_graphic() || c == ' ',
+                    "punct" => c.is_ascii_punctuation(),
+                    "space" => c.is_whitespace(),
+                    "upper" => c.is_uppercase(),
+                    _ => c.is_ascii_hexdigit(),
+                },
+            });
+            found != *negated
+        }
+    }
+}
+
+/// A member of a bracket expression as a pattern word part: bare when it has no meaning to sh,
+/// quoted otherwise. A `-` is only read here where it cannot form a range, first or last.
+fn class_char(c: char) -> WordPart {
+    if c.is_ascii_alphanumeric() || "-_.,:+@%!^]".contains(c) {
+        WordPart::Raw(c.to_string())
+    } else if c == '\'' {
+        WordPart::Raw("\\'".to_string())
+    } else {
+        WordPart::Literal(c.to_string())
+    }
+}
+
+/// Reads the bracket expression opened at `start`, returning it with the index of its closing
+/// `]`. A `]` right after the opening bracket (or its negation) is part of the set, and so is a
+/// `[` that does not open a class name.
+fn parse_class(chars: &[char], start: usize) -> Option<(Piece, usize)> {
+    let mut i = start + 1;
+    let negated = matches!(chars.get(i), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+    let first = i;
+    let mut members = vec![];
+    while i < chars.len() {
+        match chars[i] {
+            ']' if i > first => return Some((Piece::Class { negated, members }, i)),
+            '[' if chars.get(i + 1) == Some(&':') => {
+                let rest = chars[i + 2..].iter().collect::<String>();
+                let name = rest.split(":]").next().filter(|_| rest.contains(":]"))?;
+                if !CLASSES.contains(&name) {
+                    return None;
+                }
+                members.push(Member::Named(name.to_string()));
+                i += name.chars().count() + 4;
+            }
+            '\\' if i + 1 < chars.len() => {
+                members.push(Member::Char(chars[i + 1]));
+                i += 2;
+            }
+            c if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&to| to != ']') => {
+                members.push(Member::Range(c, chars[i + 2]));
+                i += 3;
+            }
+            c => {
+                members.push(Member::Char(c));
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sh_ast::printer::print_word;
+
+    #[test]
+    fn pattern_words() {
+        assert_eq!(print_word(&Pattern::parse("*.tar.gz").to_word()), "*.tar.gz");
+        assert_eq!(print_word(&Pattern::parse("a b*").to_word()), "'a b'*");
+        assert_eq!(print_word(&Pattern::parse("\\*x?").to_word()), "'*x'?");
+        assert_eq!(print_word(&Pattern::parse("[a-c]$").to_word()), "[a-c]'$'");
+        assert_eq!(print_word(&Pattern::parse("[ab").to_word()), "'[ab'");
+        assert_eq!(print_word(&Pattern::parse("[;]").to_word()), "[';']");
+        assert_eq!(print_word(&Pattern::parse("[[:digit:]]*").to_word()), "[[:digit:]]*");
+        assert_eq!(print_word(&Pattern::parse("a[ ]b").to_word()), "a[' ']b");
+        assert_eq!(print_word(&Pattern::parse("[']").to_word()), "[\\']");
+        assert_eq!(print_word(&Pattern::parse("[$]").to_word()), "['$']");
+        assert_eq!(print_word(&Pattern::parse("[!]a-]").to_word()), "[!]a-]");
+        assert_eq!(print_word(&Pattern::parse("[[:nope:]]").to_word()), "'['[:nope:]']'");
+        assert_eq!(print_word(&Pattern::parse("").to_word()), "''");
+    }
+
+    #[test]
+    fn coverage() {
+        let covered = |a: &str, b: &str| Pattern::parse(a).covered_by(&Pattern::parse(b));
+        assert!(covered("x.tar.gz", "*.tar.gz"));
+        assert!(covered("anything*", "*"));
+        assert!(covered("a?", "a?"));
+        assert!(covered("b", "[a-c]"));
+        assert!(!covered("d", "[a-c]"));
+        assert!(!covered("b", "[!a-c]"));
+        assert!(covered("7x", "[[:digit:]]*"));
+        assert!(!covered("x7", "[[:digit:]]*"));
+        assert!(covered("a b", "a[ ]b"));
+        assert!(covered("'", "[']"));
+        assert!(covered("$", "[$]"));
+        assert!(!covered("x.tar", "*.tar.gz"));
+        assert!(!covered("*.gz", "*.tar.gz"));
+    }
+}
@@ -0,0 +1,341 @@
+//! `demac` macros, which rewrite the forms calling them before anything is compiled.
+//!
+//! A macro is a template: its body is a parameter or a quasiquoted form whose unquoted parameters
+//! are replaced by the forms given as arguments, unevaluated, and spliced in for `,@`.
+
+use crate::errors::{ShispError, ShispErrorType};
+use crate::parser::form::{Form, FormType};
+use std::collections::HashMap;
+
+/// How many expansions may be nested before a macro is taken to expand into itself forever.
+const MAX_DEPTH: usize = 64;
+
+#[derive(Debug, Clone)]
+struct Macro {
+    params: Vec<String>,
+    /// The parameter after a `.`, bound to the list of the arguments left over.
+    rest: Option<String>,
+    body: Form,
+}
+
+/// The forms a parameter stands for in an expansion.
+enum Argument<'a> {
+    One(&'a Form),
+    Rest(&'a [Form]),
+}
+
+#[derive(Debug, Default)]
+pub struct Macros {
+    macros: HashMap<String, Macro>,
+}
+
+impl Macros {
+    pub fn new() -> Macros {
+        Macros::default()
+    }
+
+    /// Reads `(demac name (params... [. rest]) body)`, replacing any macro of the same name.
+    pub fn define(&mut self, form: &Form) -> Result<(), ShispError> {
+        let malformed = || form.error(ShispErrorType::MalformedForm(
+            "demac expects `(demac name (args... [. rest]) body)`".to_string(),
+        ));
+        let (name, params, body) = match form.as_list() {
+            Some([_, name, params, body]) => (
+                name.as_atom().ok_or_else(malformed)?,
+                params.as_list().ok_or_else(malformed)?,
+                body,
+            ),
+            _ => return Err(malformed()),
+        };
+        let mut names = params
+            .iter()
+            .map(|p| p.as_atom().map(str::to_string).ok_or_else(malformed))
+            .collect::<Result<Vec<String>, ShispError>>()?;
+        let rest = match &names[..] {
+            [.., dot, _] if dot == "." => {
+                let rest = names.pop();
+                names.pop();
+                rest
+            }
+            _ => None,
+        };
+        if names.iter().any(|n| n == ".") {
+            return Err(malformed());
+        }
+        self.macros.insert(name.to_string(), Macro {
+            params: names,
+            rest,
+            body: body.clone(),
+        });
+        Ok(())
+    }
+
+    /// Expands the macro calls in `form` and in the forms it holds, until none are left. Names
+    /// being bound and quoted forms are left alone.
+    pub fn expand(&self, form: &Form) -> Result<Form, ShispError> {
+        self.expand_at(form, 0)
+    }
+
+    fn expand_at(&self, form: &Form, depth: usize) -> Result<Form, ShispError> {
+        let forms = match &form.form_type {
+            FormType::List(forms) => forms,
+            FormType::Quasiquote(quoted) => {
+                let quoted = self.expand_unquoted(quoted, depth)?;
+                return Ok(with_type(form, FormType::Quasiquote(Box::new(quoted))));
+            }
+            _ => return Ok(form.clone()),
+        };
+        if let Some((name, definition)) = form.head().and_then(|h| self.macros.get_key_value(h)) {
+            if depth == MAX_DEPTH {
+                return Err(form.error(ShispErrorType::MalformedForm(format!(
+                    "the expansion of macro `{}` does not end",
+                    name
+                ))));
+            }
+            let expansion = self.apply(name, definition, form, &forms[1..])?;
+            return self.expand_at(&expansion, depth + 1);
+        }
+
+        let expand = |forms: &[Form]| {
+            forms
+                .iter()
+                .map(|f| self.expand_at(f, depth))
+                .collect::<Result<Vec<Form>, ShispError>>()
+        };
+        // Keeps the first element of each of `clauses` (a name or a pattern) and expands the rest.
+        let clauses = |clauses: &[Form]| {
+            clauses
+                .iter()
+                .map(|clause| match clause.as_list() {
+                    Some([first, rest @ ..]) => {
+                        let mut forms = vec![first.clone()];
+                        forms.extend(expand(rest)?);
+                        Ok(with_type(clause, FormType::List(forms)))
+                    }
+                    _ => Ok(clause.clone()),
+                })
+                .collect::<Result<Vec<Form>, ShispError>>()
+        };
+        // The first `n` forms as they are, followed by `rest`.
+        let kept = |n: usize, rest: Vec<Form>| forms[..n].iter().cloned().chain(rest).collect::<Vec<Form>>();
+
+        let expanded = match form.head() {
+            Some("demac") => {
+                return Err(form.error(ShispErrorType::MalformedForm(
+                    "demac can only be used at the top level".to_string(),
+                )))
+            }
+            Some("quote" | "shell-literal" | "defstruct" | "defcli") => return Ok(form.clone()),
+            Some("defun" | "depun") if forms.len() > 3 => kept(3, expand(&forms[3..])?),
+            Some("lambda") if forms.len() > 2 => kept(2, expand(&forms[2..])?),
+            Some("let") if forms.len() > 1 => {
+                let bindings = match forms[1].as_list() {
+                    Some(bindings) => with_type(&forms[1], FormType::List(clauses(bindings)?)),
+                    None => forms[1].clone(),
+                };
+                let mut expanded = kept(1, vec![bindings]);
+                expanded.extend(expand(&forms[2..])?);
+                expanded
+            }
+            Some("for-each" | "dotimes") if forms.len() > 1 => {
+                let mut expanded = kept(1, clauses(&forms[1..2])?);
+                expanded.extend(expand(&forms[2..])?);
+                expanded
+            }
+            Some("for-each-line") if forms.len() > 2 => {
+                let mut expanded = kept(1, expand(&forms[1..2])?);
+                expanded.push(forms[2].clone());
+                expanded.extend(expand(&forms[3..])?);
+                expanded
+            }
+            Some("case" | "match") if forms.len() > 1 => {
+                let mut expanded = kept(1, expand(&forms[1..2])?);
+                expanded.extend(clauses(&forms[2..])?);
+                expanded
+            }
+            _ => expand(forms)?,
+        };
+        Ok(with_type(form, FormType::List(expanded)))
+    }
+
+    /// Expands the unquoted forms of a quasiquoted `form`, which are evaluated.
+    fn expand_unquoted(&self, form: &Form, depth: usize) -> Result<Form, ShispError> {
+        let form_type = match &form.form_type {
+            FormType::Unquote(inner) => FormType::Unquote(Box::new(self.expand_at(inner, depth)?)),
+            FormType::UnquoteSplice(inner) => FormType::UnquoteSplice(Box::new(self.expand_at(inner, depth)?)),
+            FormType::List(forms) => FormType::List(
+                forms
+                    .iter()
+                    .map(|f| self.expand_unquoted(f, depth))
+                    .collect::<Result<Vec<Form>, ShispError>>()?,
+            ),
+            _ => return Ok(form.clone()),
+        };
+        Ok(with_type(form, form_type))
+    }
+
+    /// The form `call` is replaced with.
+    fn apply(&self, name: &str, definition: &Macro, call: &Form, args: &[Form]) -> Result<Form, ShispError> {
+        let required = definition.params.len();
+        if args.len() < required || (definition.rest.is_none() && args.len() > required) {
+            return Err(call.error(ShispErrorType::ArityMismatch(name.to_string(), required, args.len())));
+        }
+        let mut bindings = definition
+            .params
+            .iter()
+            .zip(args)
+            .map(|(param, arg)| (param.as_str(), Argument::One(arg)))
+            .collect::<HashMap<&str, Argument>>();
+        if let Some(rest) = &definition.rest {
+            bindings.insert(rest, Argument::Rest(&args[required..]));
+        }
+
+        let mut expansion = match &definition.body.form_type {
+            FormType::Atom(param) if bindings.contains_key(param.as_str()) => value(&bindings[param.as_str()], call),
+            FormType::Quasiquote(template) => fill(template, &bindings, call)?,
+            _ => {
+                return Err(definition.body.error(ShispErrorType::MalformedForm(
+                    "the body of a macro must be one of its parameters or a quasiquoted template".to_string(),
+                )))
+            }
+        };
+        expansion.comments = call.comments.clone();
+        Ok(expansion)
+    }
+}
+
+/// `form` with another type, keeping its position.
+fn with_type(form: &Form, form_type: FormType) -> Form {
+    Form {
+        form_type,
+        ..form.clone()
+    }
+}
+
+/// The form an argument stands for: the remaining arguments make a list.
+fn value(argument: &Argument, call: &Form) -> Form {
+    match argument {
+        Argument::One(form) => (*form).clone(),
+        Argument::Rest(forms) => placed(FormType::List(forms.to_vec()), call),
+    }
+}
+
+/// A form of the template, placed where the macro was called.
+fn placed(form_type: FormType, call: &Form) -> Form {
+    Form {
+        row: call.row,
+        col: call.col,
+        form_type,
+        comments: vec![],
+    }
+}
+
+/// Replaces the unquoted parameters of `template` with the forms bound to them.
+fn fill(template: &Form, bindings: &HashMap<&str, Argument>, call: &Form) -> Result<Form, ShispError> {
+    let bound = |form: &Form| match form.as_atom().and_then(|name| bindings.get(name)) {
+        Some(argument) => Ok(argument),
+        None => Err(form.error(ShispErrorType::MalformedForm(
+            "only the parameters of a macro can be unquoted in its template".to_string(),
+        ))),
+    };
+
+    let form_type = match &template.form_type {
+        FormType::Unquote(inner) => return Ok(value(bound(inner)?, call)),
+        FormType::UnquoteSplice(_) => {
+            return Err(template.error(ShispErrorType::MalformedForm(
+                "`,@` can only be used inside a list".to_string(),
+            )))
+        }
+        FormType::List(items) => {
+            let mut forms = vec![];
+            for item in items {
+                match &item.form_type {
+                    FormType::UnquoteSplice(inner) => match bound(inner)? {
+                        Argument::Rest(rest) => forms.extend(rest.iter().cloned()),
+                        Argument::One(form) => match form.as_list() {
+                            Some(elements) => forms.extend(elements.iter().cloned()),
+                            None => forms.push((*form).clone()),
+                        },
+                    },
+                    _ => forms.push(fill(item, bindings, call)?),
+                }
+            }
+            FormType::List(forms)
+        }
+        FormType::Quote(inner) => FormType::Quote(Box::new(fill(inner, bindings, call)?)),
+        other => other.clone(),
+    };
+    Ok(placed(form_type, call))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser::parse_tokens;
+
+    fn read(input: &str) -> Vec<Form> {
+        Form::from_ast(&parse_tokens(lexer::scan_string(input.to_string()))).unwrap()
+    }
+
+    /// Expands `input` with the macros of `definitions` and prints the result back as text.
+    fn expand(definitions: &str, input: &str) -> Result<String, ShispError> {
+        let mut macros = Macros::new();
+        for form in read(definitions) {
+            macros.define(&form)?;
+        }
+        let forms = read(input)
+            .iter()
+            .map(|f| macros.expand(f))
+            .collect::<Result<Vec<Form>, ShispError>>()?;
+        Ok(forms.iter().map(show).collect::<Vec<String>>().join(" "))
+    }
+
+    fn show(form: &Form) -> String {
+        match &form.form_type {
+            FormType::List(forms) => format!("({})", forms.iter().map(show).collect::<Vec<String>>().join(" ")),
+            FormType::Atom(name) => name.clone(),
+            FormType::Str(s) => format!("{:?}", s),
+            FormType::Number(n) => n.to_string(),
+            FormType::Boolean(b) => (if *b { "#t" } else { "#f" }).to_string(),
+            FormType::Quote(f) => format!("'{}", show(f)),
+            FormType::Quasiquote(f) => format!("`{}", show(f)),
+            FormType::Unquote(f) => format!(",{}", show(f)),
+            FormType::UnquoteSplice(f) => format!(",@{}", show(f)),
+        }
+    }
+
+    #[test]
+    fn templates() {
+        let definitions = "(demac when (test . body) `(cond (,test ,@body)))
+                           (demac unless (test . body) `(when (not ,test) ,@body))
+                           (demac quoted (x) `(f ',x))
+                           (demac same (x) x)";
+        assert_eq!(
+            expand(definitions, "(when a (f 1) 2) (unless (same b)) (quoted (g x))").unwrap(),
+            "(cond (a (f 1) 2)) (cond ((not b))) (f '(g x))"
+        );
+        assert_eq!(
+            expand(definitions, "(defun when (when) (when x y)) (let ((when (when p q))) '(when a))").unwrap(),
+            "(defun when (when) (cond (x y))) (let ((when (cond (p q)))) '(when a))"
+        );
+        assert_eq!(expand(definitions, "`(a ,(when b c))").unwrap(), "`(a ,(cond (b c)))");
+    }
+
+    #[test]
+    fn errors() {
+        let error = expand("(demac two (a b) `(a ,b))", "(two 1)").unwrap_err();
+        assert_eq!(error.error_type(), &ShispErrorType::ArityMismatch("two".to_string(), 2, 1));
+
+        for (definitions, input) in [
+            ("(demac m (x) (f x))", "(m 1)"),
+            ("(demac m (x) `(f ,(g x)))", "(m 1)"),
+            ("(demac m (x) `(m ,x))", "(m 1)"),
+            ("(demac m (a . b . c) a)", "(m 1)"),
+            ("", "(f (demac m (x) x))"),
+        ] {
+            let error = expand(definitions, input).unwrap_err();
+            assert!(matches!(error.error_type(), ShispErrorType::MalformedForm(_)), "{}", definitions);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+//! The prelude, `prelude.shisp`: macros such as `when` and `unless` and functions on lists such
+//! as `map` and `fold`, written in Shisp and embedded in the compiler.
+
+use super::macros::Macros;
+use super::reachability;
+use crate::errors::ShispError;
+use crate::lexer;
+use crate::parser;
+use crate::parser::form::Form;
+use std::collections::BTreeSet;
+
+const PRELUDE: &str = include_str!("prelude.shisp");
+
+/// Expands the macros of the prelude and of `forms`, returning the functions of the prelude that
+/// `forms` use, directly or through each other, and `forms` without their `demac`s.
+///
+/// The program's own definitions replace the prelude's of the same name.
+pub fn expand(forms: Vec<Form>) -> Result<(Vec<Form>, Vec<Form>), ShispError> {
+    let prelude = Form::from_ast(&parser::parse_tokens(lexer::scan_string(PRELUDE.to_string())))
+        .expect("the prelude can be read");
+    let (prelude_macros, functions): (Vec<Form>, Vec<Form>) =
+        prelude.into_iter().partition(|form| form.head() == Some("demac"));
+    let (program_macros, forms): (Vec<Form>, Vec<Form>) =
+        forms.into_iter().partition(|form| form.head() == Some("demac"));
+    let defined = forms.iter().chain(&program_macros).filter_map(defined_name).collect::<BTreeSet<&str>>();
+    let shadowed = |form: &Form| defined_name(form).is_some_and(|name| defined.contains(name));
+
+    // The prelude's functions are expanded with its own macros only, whatever the program defines.
+    let mut macros = Macros::new();
+    for definition in &prelude_macros {
+        macros.define(definition)?;
+    }
+    let functions = functions
+        .iter()
+        .filter(|form| !shadowed(form))
+        .map(|form| Ok(Form { comments: vec![], ..macros.expand(form)? }))
+        .collect::<Result<Vec<Form>, ShispError>>()?;
+
+    let mut macros = Macros::new();
+    for definition in prelude_macros.iter().filter(|form| !shadowed(form)).chain(&program_macros) {
+        macros.define(definition)?;
+    }
+    let program = forms
+        .iter()
+        .map(|form| macros.expand(form))
+        .collect::<Result<Vec<Form>, ShispError>>()?;
+
+    let used = reachability::used(&program, &functions.iter().collect::<Vec<&Form>>());
+    let library = functions
+        .iter()
+        .filter(|form| defined_name(form).is_some_and(|name| used.contains(name)))
+        .cloned()
+        .collect();
+    Ok((library, program))
+}
+
+/// The name a top-level definition gives a function, variable or macro.
+fn defined_name(form: &Form) -> Option<&str> {
+    match form.head() {
+        Some("defun" | "depun" | "define" | "demac") => form.as_list()?.get(1)?.as_atom(),
+        _ => None,
+    }
+}
@@ -0,0 +1,94 @@
+//! Which top-level functions a program uses, following the calls from the forms the program runs
+//! through the functions they reach.
+
+use crate::parser::form::{Form, FormType};
+use std::collections::BTreeSet;
+
+/// The names of the `functions` used by `roots`, directly or through each other. Every atom
+/// outside of a quote counts as a use, so a function passed around as a value is kept, and so is
+/// one sharing its name with a variable that is used.
+pub fn used<'a>(roots: impl IntoIterator<Item = &'a Form>, functions: &[&'a Form]) -> BTreeSet<&'a str> {
+    let mut names = BTreeSet::new();
+    for root in roots {
+        references(root, &mut names);
+    }
+    let mut used = BTreeSet::new();
+    while let Some((name, function)) = functions
+        .iter()
+        .filter_map(|f| Some((function_name(f)?, *f)))
+        .find(|(name, _)| names.contains(name) && !used.contains(name))
+    {
+        used.insert(name);
+        references(function, &mut names);
+    }
+    used
+}
+
+/// The names of the `defun`s and `depun`s among the top-level `forms` that are never used by the
+/// other forms, or by the functions they use.
+pub fn unused<'a>(forms: impl IntoIterator<Item = &'a Form>) -> BTreeSet<&'a str> {
+    let (functions, roots): (Vec<&Form>, Vec<&Form>) = forms.into_iter().partition(|f| function_name(f).is_some());
+    let used = used(roots, &functions);
+    functions
+        .iter()
+        .filter_map(|f| function_name(f))
+        .filter(|name| !used.contains(name))
+        .collect()
+}
+
+fn function_name(form: &Form) -> Option<&str> {
+    match form.head() {
+        Some("defun" | "depun") => form.as_list()?.get(1)?.as_atom(),
+        _ => None,
+    }
+}
+
+/// Adds the atoms of `form` outside of quotes to `names`: those of the functions it calls or uses
+/// as values, among others.
+fn references<'a>(form: &'a Form, names: &mut BTreeSet<&'a str>) {
+    match &form.form_type {
+        FormType::Atom(name) => {
+            names.insert(name);
+        }
+        FormType::List(forms) if form.head() != Some("quote") => {
+            for form in forms {
+                references(form, names);
+            }
+        }
+        FormType::Quasiquote(inner) | FormType::Unquote(inner) | FormType::UnquoteSplice(inner) => {
+            references(inner, names)
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser::parse_tokens;
+
+    fn unused_in(input: &str) -> Vec<String> {
+        let forms = Form::from_ast(&parse_tokens(lexer::scan_string(input.to_string()))).unwrap();
+        unused(&forms).into_iter().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn call_graph() {
+        let input = "
+            (defun main () (helper (lambda (x) (leaf x))))
+            (defun helper (f) (funcall f 1))
+            (defun leaf (x) x)
+            (defun dead () (dead-too))
+            (defun dead-too () (dead))
+            (defun recursive (n) (recursive n))
+            (defun quoted () 1)
+            (define handler callback)
+            (defun callback () 2)
+            (main '(quoted))
+        ";
+        assert_eq!(unused_in(input), vec!["dead", "dead-too", "quoted", "recursive"]);
+        assert_eq!(unused_in("(defun f () 1)"), vec!["f"]);
+        assert!(unused_in("(defun f () 1) `(a ,(f))").is_empty());
+    }
+}
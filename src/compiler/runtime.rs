@@ -0,0 +1,455 @@
+use std::collections::BTreeSet;
+
+/// Support functions every generated script starts with.
+///
+/// `shisp_save` and `shisp_restore` give function locals their own value per call: POSIX sh has
+/// no `local`, so a function pushes the previous values of its locals on entry and pops them back
+/// on exit.
+///
+/// Lists are chains of cons cells. A cell is a pair of variables `shisp_car_N` and `shisp_cdr_N`,
+/// and the value referring to it is `shisp_cN`; the empty list is the empty string.
+///
+/// Records of a `defstruct` are numbered from the same counter as cons cells. The value referring
+/// to a record is `shisp_oN`, and its fields are the variables `shisp_f_N_I`, where field 0 holds
+/// the name of its type. `shisp_field` and `shisp_set_field` check that type and give nil for
+/// anything else, as `shisp_car` does for what is not a list.
+///
+/// `shisp_protect` pushes the cleanup function of an `unwind-protect` on the `shisp_unwind` stack
+/// and makes sure the stack is run when the script exits; `shisp_unprotect` pops and runs one.
+///
+/// `shisp_basename` and `shisp_dirname` work like the POSIX commands, with parameter expansions
+/// only: trailing slashes are ignored, and a path of nothing but slashes is `/`. The extension
+/// `shisp_path_extension` gives is what follows the last dot of the base name, if that dot is not
+/// its first character. `shisp_path_join` joins paths with single slashes, starting over at an
+/// absolute one.
+///
+/// The string functions only use parameter expansions, taking one character at a time with
+/// `${s#?}` where they need to count. `shisp_substring` takes an empty end as the end of the
+/// string, and `shisp_string_split` splits on every character when the separator is empty.
+///
+/// `shisp_list` makes a list of its arguments, and `shisp_return` fails with the status it is
+/// given. `shisp_funcall` calls a function value: either the name of an sh function, or a
+/// closure, a list of the name of a lifted `lambda` and its captured values, which it is given as
+/// first argument.
+pub const RUNTIME: &str = r#"shisp_sp=0
+shisp_hp=0
+shisp_r=
+shisp_unwind=
+shisp_save() {
+    for shisp_v in "$@"; do
+        shisp_sp=$((shisp_sp + 1))
+        eval "shisp_st_${shisp_sp}=\${${shisp_v}-}"
+    done
+}
+shisp_restore() {
+    shisp_i=$((shisp_sp - $# + 1))
+    for shisp_v in "$@"; do
+        eval "${shisp_v}=\${shisp_st_${shisp_i}}"
+        unset "shisp_st_${shisp_i}"
+        shisp_i=$((shisp_i + 1))
+    done
+    shisp_sp=$((shisp_sp - $#))
+}
+shisp_cons() {
+    shisp_hp=$((shisp_hp + 1))
+    eval "shisp_car_${shisp_hp}=\$1; shisp_cdr_${shisp_hp}=\$2"
+    shisp_r=shisp_c${shisp_hp}
+}
+shisp_car() {
+    case $1 in
+        shisp_c[0-9]*) eval "shisp_r=\${shisp_car_${1#shisp_c}}" ;;
+        *) shisp_r= ;;
+    esac
+}
+shisp_cdr() {
+    case $1 in
+        shisp_c[0-9]*) eval "shisp_r=\${shisp_cdr_${1#shisp_c}}" ;;
+        *) shisp_r= ;;
+    esac
+}
+shisp_list() {
+    shisp_l=
+    shisp_i=$#
+    while [ "$shisp_i" -gt 0 ]; do
+        eval "shisp_cons \"\${$shisp_i}\" \"\$shisp_l\""
+        shisp_l=$shisp_r
+        shisp_i=$((shisp_i - 1))
+    done
+    shisp_r=$shisp_l
+}
+shisp_new() {
+    shisp_hp=$((shisp_hp + 1))
+    shisp_i=0
+    for shisp_v in "$@"; do
+        eval "shisp_f_${shisp_hp}_${shisp_i}=\$shisp_v"
+        shisp_i=$((shisp_i + 1))
+    done
+    shisp_r=shisp_o${shisp_hp}
+}
+shisp_is() {
+    case $2 in
+        shisp_o[0-9]*) eval "shisp_r=\${shisp_f_${2#shisp_o}_0}" ;;
+        *) shisp_r= ;;
+    esac
+    if [ "$shisp_r" = "$1" ]; then
+        shisp_r='#t'
+    else
+        shisp_r=
+    fi
+}
+shisp_field() {
+    shisp_is "$1" "$2"
+    if [ -n "$shisp_r" ]; then
+        eval "shisp_r=\${shisp_f_${2#shisp_o}_$3}"
+    fi
+}
+shisp_set_field() {
+    shisp_is "$1" "$2"
+    if [ -n "$shisp_r" ]; then
+        eval "shisp_f_${2#shisp_o}_$3=\$4"
+        shisp_r=$4
+    fi
+}
+shisp_trim_slashes() {
+    shisp_r=$1
+    while [ "${shisp_r%/}" != "$shisp_r" ]; do
+        shisp_r=${shisp_r%/}
+    done
+}
+shisp_basename() {
+    shisp_trim_slashes "$1"
+    if [ -n "$shisp_r" ]; then
+        shisp_r=${shisp_r##*/}
+    elif [ -n "$1" ]; then
+        shisp_r=/
+    fi
+}
+shisp_dirname() {
+    shisp_trim_slashes "$1"
+    case $shisp_r in
+        */*)
+            shisp_trim_slashes "${shisp_r%/*}"
+            if [ -z "$shisp_r" ]; then
+                shisp_r=/
+            fi
+            ;;
+        '')
+            if [ -n "$1" ]; then
+                shisp_r=/
+            else
+                shisp_r=.
+            fi
+            ;;
+        *) shisp_r=. ;;
+    esac
+}
+shisp_path_extension() {
+    shisp_basename "$1"
+    case $shisp_r in
+        ?*.*) shisp_r=${shisp_r##*.} ;;
+        *) shisp_r= ;;
+    esac
+}
+shisp_path_join() {
+    shisp_r=
+    for shisp_v in "$@"; do
+        case $shisp_v in
+            '') ;;
+            /*) shisp_r=$shisp_v ;;
+            *)
+                case $shisp_r in
+                    ''|*/) shisp_r=$shisp_r$shisp_v ;;
+                    *) shisp_r=$shisp_r/$shisp_v ;;
+                esac
+                ;;
+        esac
+    done
+}
+shisp_substring() {
+    shisp_s=$1
+    shisp_i=0
+    while [ "$shisp_i" -lt "$2" ] && [ -n "$shisp_s" ]; do
+        shisp_s=${shisp_s#?}
+        shisp_i=$((shisp_i + 1))
+    done
+    if [ -z "$3" ]; then
+        shisp_r=$shisp_s
+    else
+        shisp_r=
+        while [ "$shisp_i" -lt "$3" ] && [ -n "$shisp_s" ]; do
+            shisp_x=${shisp_s#?}
+            shisp_r=$shisp_r${shisp_s%"$shisp_x"}
+            shisp_s=$shisp_x
+            shisp_i=$((shisp_i + 1))
+        done
+    fi
+}
+shisp_string_replace() {
+    shisp_r=
+    shisp_s=$1
+    while [ -n "$2" ]; do
+        case $shisp_s in
+            *"$2"*)
+                shisp_r=$shisp_r${shisp_s%%"$2"*}$3
+                shisp_s=${shisp_s#*"$2"}
+                ;;
+            *) break ;;
+        esac
+    done
+    shisp_r=$shisp_r$shisp_s
+}
+shisp_string_split() {
+    shisp_s=$1
+    shisp_x=$2
+    shift 2
+    if [ -z "$shisp_x" ]; then
+        while [ -n "$shisp_s" ]; do
+            shisp_x=${shisp_s#?}
+            set -- "$@" "${shisp_s%"$shisp_x"}"
+            shisp_s=$shisp_x
+        done
+    else
+        while :; do
+            case $shisp_s in
+                *"$shisp_x"*)
+                    set -- "$@" "${shisp_s%%"$shisp_x"*}"
+                    shisp_s=${shisp_s#*"$shisp_x"}
+                    ;;
+                *) break ;;
+            esac
+        done
+        set -- "$@" "$shisp_s"
+    fi
+    shisp_list "$@"
+}
+shisp_string_join() {
+    shisp_s=
+    shisp_x=$1
+    while [ -n "$shisp_x" ]; do
+        shisp_car "$shisp_x"
+        if [ "$shisp_x" = "$1" ]; then
+            shisp_s=$shisp_r
+        else
+            shisp_s=$shisp_s$2$shisp_r
+        fi
+        shisp_cdr "$shisp_x"
+        shisp_x=$shisp_r
+    done
+    shisp_r=$shisp_s
+}
+shisp_protect() {
+    shisp_unwind="$1 $shisp_unwind"
+    trap shisp_unwind_all EXIT
+    trap 'exit 129' HUP
+    trap 'exit 130' INT
+    trap 'exit 143' TERM
+}
+shisp_unprotect() {
+    shisp_h=${shisp_unwind%% *}
+    shisp_unwind=${shisp_unwind#* }
+    if [ -z "$shisp_unwind" ]; then
+        trap - EXIT HUP INT TERM
+    fi
+    "$shisp_h"
+}
+shisp_unwind_all() {
+    while [ -n "$shisp_unwind" ]; do
+        shisp_unprotect
+    done
+}
+shisp_return() {
+    return "$1"
+}
+shisp_funcall() {
+    shisp_f=$1
+    shift
+    case $shisp_f in
+        shisp_c[0-9]*)
+            shisp_cdr "$shisp_f"
+            shisp_e=$shisp_r
+            shisp_car "$shisp_f"
+            "$shisp_r" "$shisp_e" "$@"
+            ;;
+        *) "$shisp_f" "$@" ;;
+    esac
+}
+"#;
+
+/// The maps of `make-map`, added to the runtime of programs that use them. A map is `shisp_mN`,
+/// with `shisp_mn_N` entries whose keys and values are `shisp_mk_N_I` and `shisp_mv_N_I`. Lookups
+/// compare the key with each one in turn, so keys can hold any character, and `shisp_map_keys`
+/// lists them in the order they were first put.
+pub const MAPS: &str = r#"shisp_map_new() {
+    shisp_hp=$((shisp_hp + 1))
+    eval "shisp_mn_${shisp_hp}=0"
+    shisp_r=shisp_m${shisp_hp}
+}
+shisp_map_find() {
+    shisp_i=0
+    case $1 in
+        shisp_m[0-9]*) shisp_m=${1#shisp_m} ;;
+        *) shisp_m= ; return ;;
+    esac
+    eval "shisp_i=\$shisp_mn_${shisp_m}"
+    while [ "$shisp_i" -gt 0 ]; do
+        eval "shisp_k=\$shisp_mk_${shisp_m}_${shisp_i}"
+        if [ "$shisp_k" = "$2" ]; then
+            return
+        fi
+        shisp_i=$((shisp_i - 1))
+    done
+}
+shisp_map_get() {
+    shisp_map_find "$1" "$2"
+    shisp_r=
+    if [ "$shisp_i" -gt 0 ]; then
+        eval "shisp_r=\$shisp_mv_${shisp_m}_${shisp_i}"
+    fi
+}
+shisp_map_has() {
+    shisp_map_find "$1" "$2"
+    shisp_r=
+    if [ "$shisp_i" -gt 0 ]; then
+        shisp_r='#t'
+    fi
+}
+shisp_map_put() {
+    shisp_map_find "$1" "$2"
+    shisp_r=
+    if [ -n "$shisp_m" ]; then
+        if [ "$shisp_i" -eq 0 ]; then
+            eval "shisp_i=\$((shisp_mn_${shisp_m} + 1)); shisp_mn_${shisp_m}=\$shisp_i"
+            eval "shisp_mk_${shisp_m}_${shisp_i}=\$2"
+        fi
+        eval "shisp_mv_${shisp_m}_${shisp_i}=\$3"
+        shisp_r=$3
+    fi
+}
+shisp_map_keys() {
+    shisp_map_find "$1" ''
+    shisp_l=
+    if [ -n "$shisp_m" ]; then
+        eval "shisp_i=\$shisp_mn_${shisp_m}"
+        while [ "$shisp_i" -gt 0 ]; do
+            eval "shisp_cons \"\$shisp_mk_${shisp_m}_${shisp_i}\" \"\$shisp_l\""
+            shisp_l=$shisp_r
+            shisp_i=$((shisp_i - 1))
+        done
+    fi
+    shisp_r=$shisp_l
+}
+"#;
+
+/// The same functions over bash associative arrays, for targets that have them. A map is
+/// `shisp_mN`, an array `shisp_m_N` holding each value under its key prefixed with `k`, as bash
+/// rejects an empty subscript. The keys are listed in no particular order.
+pub const ASSOCIATIVE_MAPS: &str = r#"shisp_map_new() {
+    shisp_hp=$((shisp_hp + 1))
+    declare -gA "shisp_m_${shisp_hp}"
+    shisp_r=shisp_m${shisp_hp}
+}
+shisp_map_get() {
+    case $1 in
+        shisp_m[0-9]*) eval "shisp_r=\${shisp_m_${1#shisp_m}[k\$2]-}" ;;
+        *) shisp_r= ;;
+    esac
+}
+shisp_map_has() {
+    shisp_r=
+    case $1 in
+        shisp_m[0-9]*) eval "shisp_r=\${shisp_m_${1#shisp_m}[k\$2]+#t}" ;;
+    esac
+}
+shisp_map_put() {
+    shisp_r=
+    case $1 in
+        shisp_m[0-9]*)
+            eval "shisp_m_${1#shisp_m}[k\$2]=\$3"
+            shisp_r=$3
+            ;;
+    esac
+}
+shisp_map_keys() {
+    shisp_l=
+    case $1 in
+        shisp_m[0-9]*)
+            eval "set -- \"\${!shisp_m_${1#shisp_m}[@]}\""
+            for shisp_k in "$@"; do
+                shisp_cons "${shisp_k#k}" "$shisp_l"
+                shisp_l=$shisp_r
+            done
+            ;;
+    esac
+    shisp_r=$shisp_l
+}
+"#;
+
+/// The part of `runtime` the script `code` needs, and the names of the functions left out. The
+/// variables are always kept, and a function is kept when its name is a word of `code` or of
+/// another function kept.
+pub fn used(runtime: &str, code: &str) -> (String, Vec<String>) {
+    let mut kept = String::new();
+    let mut functions: Vec<(&str, String)> = vec![];
+    let mut current: Option<(&str, String)> = None;
+    for line in runtime.lines() {
+        match current.as_mut() {
+            Some((_, body)) => {
+                body.push_str(line);
+                body.push('\n');
+                if line == "}" {
+                    functions.extend(current.take());
+                }
+            }
+            None => match line.strip_suffix("() {") {
+                Some(name) => current = Some((name, format!("{}\n", line))),
+                None => {
+                    kept.push_str(line);
+                    kept.push('\n');
+                }
+            },
+        }
+    }
+
+    let mut names = words(code);
+    let mut used = vec![false; functions.len()];
+    while let Some(i) = (0..functions.len()).find(|i| !used[*i] && names.contains(functions[*i].0)) {
+        used[i] = true;
+        names.extend(words(&functions[i].1));
+    }
+    let mut removed = vec![];
+    for ((name, body), used) in functions.into_iter().zip(used) {
+        match used {
+            true => kept.push_str(&body),
+            false => removed.push(name.to_string()),
+        }
+    }
+    (kept, removed)
+}
+
+fn words(text: &str) -> BTreeSet<&str> {
+    text.split(|c: char| !c.is_ascii_alphanumeric() && c != '_').collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn used_functions() {
+        let (kept, removed) = used(RUNTIME, "shisp_list a b\nshisp_cdr \"$shisp_r\"\n");
+        assert!(kept.starts_with("shisp_sp=0\nshisp_hp=0\nshisp_r=\nshisp_unwind=\n"));
+        for name in ["shisp_list", "shisp_cons", "shisp_cdr"] {
+            assert!(kept.contains(&format!("\n{}() {{\n", name)), "{}", name);
+        }
+        assert!(removed.contains(&"shisp_car".to_string()) && !kept.contains("shisp_car()"));
+        assert_eq!(removed.len() + 3, RUNTIME.matches("() {\n").count());
+
+        let (kept, _) = used(RUNTIME, "shisp_protect cleanup\n");
+        assert!(kept.contains("shisp_unwind_all() {") && kept.contains("shisp_unprotect() {"));
+        assert!(!kept.contains("shisp_cons() {"));
+
+        let (kept, removed) = used(RUNTIME, "");
+        assert!(!kept.contains("() {"));
+        assert_eq!(removed[0], "shisp_save");
+    }
+}
@@ -0,0 +1,213 @@
+use crate::errors::ShispErrorType;
+
+/// The quoting context of a position in sh text.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Context {
+    Unquoted,
+    Single,
+    Double,
+    /// Inside `$(...)`, with the number of unclosed inner parentheses.
+    Substitution(usize),
+}
+
+/// Replaces the `,name` and `,{name}` references in a `shell-literal` with the sh variable bound to
+/// `name`, quoted so that its value is always used as a single word whatever the surrounding
+/// quoting is. `,,` stands for a literal comma and any other comma is left untouched.
+///
+/// The short form only takes letters, digits, `-` and `_`; other names need the braces.
+pub fn interpolate<F>(text: &str, mut resolve: F) -> Result<String, ShispErrorType>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut contexts = vec![Context::Unquoted];
+    let mut out = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+        let context = *contexts.last().unwrap();
+
+        if c == ',' {
+            let (name, len) = match reference(&chars, i)? {
+                Comma::Reference(name, len) => (name, len),
+                Comma::Escaped => {
+                    out.push(',');
+                    i += 2;
+                    continue;
+                }
+                Comma::Plain => {
+                    out.push(',');
+                    i += 1;
+                    continue;
+                }
+            };
+
+            let var = resolve(&name).ok_or(ShispErrorType::UnboundVariable(name))?;
+            out.push_str(&match context {
+                Context::Double => format!("${{{}}}", var),
+                Context::Single => format!("'\"${{{}}}\"'", var),
+                Context::Unquoted | Context::Substitution(_) => format!("\"${{{}}}\"", var),
+            });
+            i += len;
+            continue;
+        }
+
+        out.push(c);
+        i += 1;
+        match (context, c) {
+            (Context::Single, '\'') => {
+                contexts.pop();
+            }
+            (Context::Single, _) => {}
+            (_, '\\') => {
+                if let Some(n) = next {
+                    out.push(n);
+                    i += 1;
+                }
+            }
+            (Context::Double, '"') => {
+                contexts.pop();
+            }
+            (_, '$') if next == Some('(') => {
+                out.push('(');
+                i += 1;
+                contexts.push(Context::Substitution(0));
+            }
+            (Context::Double, _) => {}
+            (_, '\'') => contexts.push(Context::Single),
+            (_, '"') => contexts.push(Context::Double),
+            (Context::Substitution(depth), '(') => {
+                *contexts.last_mut().unwrap() = Context::Substitution(depth + 1);
+            }
+            (Context::Substitution(0), ')') => {
+                contexts.pop();
+            }
+            (Context::Substitution(depth), ')') => {
+                *contexts.last_mut().unwrap() = Context::Substitution(depth - 1);
+            }
+            _ => {}
+        }
+    }
+    Ok(out)
+}
+
+/// Replaces the `,name` and `,{name}` references in the text of a `heredoc-interpolate` with the sh
+/// variable bound to `name`, as `interpolate` does, and escapes what an unquoted here-document
+/// would expand otherwise, so that the variables are the only thing substituted.
+pub fn interpolate_heredoc<F>(text: &str, mut resolve: F) -> Result<String, ShispErrorType>
+where
+    F: FnMut(&str) -> Option<String>,
+{
+    let chars = text.chars().collect::<Vec<char>>();
+    let mut out = String::new();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            ',' => match reference(&chars, i)? {
+                Comma::Reference(name, len) => {
+                    let var = resolve(&name).ok_or(ShispErrorType::UnboundVariable(name))?;
+                    out.push_str(&format!("${{{}}}", var));
+                    i += len;
+                    continue;
+                }
+                Comma::Escaped => i += 1,
+                Comma::Plain => {}
+            },
+            c @ ('$' | '`' | '\\') => {
+                out.push('\\');
+                out.push(c);
+                i += 1;
+                continue;
+            }
+            _ => {}
+        }
+        out.push(chars[i]);
+        i += 1;
+    }
+    Ok(out)
+}
+
+/// What the comma at `chars[i]` starts.
+enum Comma {
+    /// A reference to a name, with the length of the reference.
+    Reference(String, usize),
+    /// `,,`, a literal comma.
+    Escaped,
+    /// Nothing but itself.
+    Plain,
+}
+
+fn reference(chars: &[char], i: usize) -> Result<Comma, ShispErrorType> {
+    match chars.get(i + 1) {
+        Some(',') => Ok(Comma::Escaped),
+        Some('{') => match chars[i + 2..].iter().position(|c| *c == '}') {
+            Some(end) => Ok(Comma::Reference(chars[i + 2..i + 2 + end].iter().collect(), end + 3)),
+            None => Err(ShispErrorType::MalformedForm("unterminated `,{` reference".to_string())),
+        },
+        Some(n) if n.is_ascii_alphabetic() || *n == '_' => {
+            let name = chars[i + 1..].iter().take_while(|c| is_name_char(**c)).collect::<String>();
+            let len = name.chars().count() + 1;
+            Ok(Comma::Reference(name, len))
+        }
+        _ => Ok(Comma::Plain),
+    }
+}
+
+fn is_name_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(name: &str) -> Option<String> {
+        match name {
+            "name" => Some("s_name".to_string()),
+            "file-name" => Some("s_file_2dname".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn quoting_contexts() {
+        let cases = [
+            ("echo ,name", "echo \"${s_name}\""),
+            ("echo \"hello ,name!\"", "echo \"hello ${s_name}!\""),
+            ("echo 'hello ,name'", "echo 'hello '\"${s_name}\"''"),
+            ("echo \"$(basename ,file-name)\"", "echo \"$(basename \"${s_file_2dname}\")\""),
+            ("echo ,{name}.txt", "echo \"${s_name}\".txt"),
+        ];
+
+        for (input, output) in cases {
+            assert_eq!(interpolate(input, resolve).unwrap(), output);
+        }
+    }
+
+    #[test]
+    fn literal_commas() {
+        assert_eq!(interpolate("cut -d, -f1", resolve).unwrap(), "cut -d, -f1");
+        assert_eq!(interpolate("echo a,,b", resolve).unwrap(), "echo a,b");
+        assert_eq!(interpolate("echo \\,name", resolve).unwrap(), "echo \\,name");
+    }
+
+    #[test]
+    fn unbound() {
+        assert_eq!(
+            interpolate("echo ,missing", resolve),
+            Err(ShispErrorType::UnboundVariable("missing".to_string()))
+        );
+        assert!(interpolate("echo ,{name", resolve).is_err());
+    }
+
+    #[test]
+    fn heredoc_text() {
+        assert_eq!(
+            interpolate_heredoc("user=,name `id` $HOME \\ a,,b a, ,{file-name}\n", resolve).unwrap(),
+            "user=${s_name} \\`id\\` \\$HOME \\\\ a,b a, ${s_file_2dname}\n"
+        );
+        assert!(interpolate_heredoc(",missing", resolve).is_err());
+    }
+}
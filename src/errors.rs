@@ -1,21 +1,161 @@
+use std::fmt;
+use crate::target::{Feature, Target};
+
 // #[non_exhaustive]
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ShispErrorType {
     EOF,
+    UnboundVariable(String),
+    UnboundFunction(String),
+    MalformedForm(String),
+    ArityMismatch(String, usize, usize),
+    ShellSyntax(String),
 }
 
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ShispError {
     error_type: ShispErrorType,
+    row: (usize, usize),
+    col: (usize, usize),
 }
 
 
 impl ShispError {
     pub fn new(error_type: ShispErrorType) -> ShispError{
         ShispError {
-            error_type
+            error_type,
+            row: (0, 0),
+            col: (0, 0),
+        }
+    }
+
+    pub fn at(error_type: ShispErrorType, row: (usize, usize), col: (usize, usize)) -> ShispError {
+        ShispError {
+            error_type,
+            row,
+            col,
+        }
+    }
+
+    pub fn error_type(&self) -> &ShispErrorType {
+        &self.error_type
+    }
+
+    pub fn position(&self) -> ((usize, usize), (usize, usize)) {
+        (self.row, self.col)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ShispWarningType {
+    Bashism(String),
+    Unsupported(Feature, Target),
+    /// A recursive call not in tail position, which stays a real call.
+    UnconvertedRecursion(String),
+    /// A `case` clause whose patterns are all matched by earlier clauses.
+    UnreachableClause,
+    /// A `match` without a clause matching every value.
+    NonExhaustiveMatch,
+    /// A function the program never uses, which is left out of the script.
+    UnusedFunction(String),
+}
+
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShispWarning {
+    warning_type: ShispWarningType,
+    row: (usize, usize),
+    col: (usize, usize),
+}
+
+
+impl ShispWarning {
+    pub fn at(warning_type: ShispWarningType, row: (usize, usize), col: (usize, usize)) -> ShispWarning {
+        ShispWarning {
+            warning_type,
+            row,
+            col,
         }
     }
+
+    pub fn warning_type(&self) -> &ShispWarningType {
+        &self.warning_type
+    }
+
+    pub fn position(&self) -> ((usize, usize), (usize, usize)) {
+        (self.row, self.col)
+    }
+
+    /// Whether this is a note about the generated code rather than a likely mistake. Notes are only
+    /// shown on request.
+    pub fn is_note(&self) -> bool {
+        matches!(
+            self.warning_type,
+            ShispWarningType::UnconvertedRecursion(_) | ShispWarningType::UnusedFunction(_)
+        )
+    }
+}
+
+impl fmt::Display for ShispErrorType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShispErrorType::EOF => write!(f, "unexpected end of input"),
+            ShispErrorType::UnboundVariable(name) => write!(f, "unbound variable `{}`", name),
+            ShispErrorType::UnboundFunction(name) => write!(
+                f,
+                "unbound function `{}` (programs are called with `(run \"{}\" ...)`)",
+                name, name
+            ),
+            ShispErrorType::MalformedForm(msg) => write!(f, "malformed form: {}", msg),
+            ShispErrorType::ArityMismatch(name, expected, found) => write!(
+                f,
+                "`{}` expects {} argument(s) but was given {}",
+                name, expected, found
+            ),
+            ShispErrorType::ShellSyntax(msg) => write!(f, "sh syntax error in shell-literal: {}", msg),
+        }
+    }
+}
+
+impl fmt::Display for ShispError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.row.0 + 1, self.col.0 + 1, self.error_type)
+    }
+}
+
+impl fmt::Display for ShispWarningType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ShispWarningType::Bashism(msg) => write!(f, "{} (shell-literal)", msg),
+            ShispWarningType::Unsupported(feature, target) => {
+                write!(f, "{} does not support {} (shell-literal)", target, feature)
+            }
+            ShispWarningType::UnconvertedRecursion(name) => write!(
+                f,
+                "this call of `{}` to itself is not in tail position, so it could not become a loop",
+                name
+            ),
+            ShispWarningType::NonExhaustiveMatch => write!(
+                f,
+                "this match has no clause matching every value, such as `(_ ...)`, so it is nil when nothing matches"
+            ),
+            ShispWarningType::UnusedFunction(name) => write!(
+                f,
+                "`{}` is never used, so it was left out of the script (`--keep-all` keeps it)",
+                name
+            ),
+            ShispWarningType::UnreachableClause => {
+                write!(f, "this case clause can never be taken, as earlier clauses match all its patterns")
+            }
+        }
+    }
+}
+
+impl fmt::Display for ShispWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.is_note() { "note" } else { "warning" };
+        write!(f, "{}:{}: {}: {}", self.row.0 + 1, self.col.0 + 1, kind, self.warning_type)
+    }
 }
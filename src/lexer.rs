@@ -5,18 +5,33 @@ pub mod tokens;
 use tokens::Token;
 
 lazy_static! {
-    static ref NUM: Regex = Regex::new("[1-9]+[0-9]*").unwrap();
-    static ref COM: Regex = Regex::new(r";.*\n?+").unwrap();
-    static ref STR: Regex = Regex::new("\"[^\"]*\"").unwrap();
+    static ref NUM: Regex = Regex::new("^[0-9]+$").unwrap();
+    static ref COM: Regex = Regex::new(r"^;.*\n?+").unwrap();
+    static ref STR: Regex = Regex::new("(?s)^\".*\"$").unwrap();
     static ref WHI: Regex = Regex::new(r"\s").unwrap();
 }
 
 pub fn scan_string(input: String) -> Vec<Token> {
     let mut curr_str = String::new();
+    let mut str_start = (0, 0);
     let mut tokens = vec![];
 
     for (li, line) in input.lines().enumerate() {
         for (ci, c) in line.chars().enumerate() {
+            if curr_str.starts_with('"') {
+                curr_str.push(c);
+                if c == '"' && !is_escaped(&curr_str[..curr_str.len() - 1]) {
+                    tokens.push(Token::new((str_start.0, li), (str_start.1, ci), curr_str));
+                    curr_str = String::new();
+                }
+                continue;
+            }
+
+            if curr_str.starts_with(';') {
+                curr_str.push(c);
+                continue;
+            }
+
             let next_char = line.chars().nth(ci + 1);
             let complex_special = match next_char {
                 Some(nc) => {
@@ -29,15 +44,17 @@ pub fn scan_string(input: String) -> Vec<Token> {
                 None => false,
             };
 
-            if WHI.is_match(&c.to_string()) && curr_str.chars().count() > 0 {
-                let offset = if ci >= curr_str.chars().count() {
-                    curr_str.chars().count()
-                } else {
-                    0
-                };
+            if WHI.is_match(&c.to_string()) {
+                if curr_str.chars().count() > 0 {
+                    let offset = if ci >= curr_str.chars().count() {
+                        curr_str.chars().count()
+                    } else {
+                        0
+                    };
 
-                tokens.push(Token::new((li, li), (ci - offset, ci - 1), curr_str));
-                curr_str = String::new();
+                    tokens.push(Token::new((li, li), (ci - offset, ci - 1), curr_str));
+                    curr_str = String::new();
+                }
                 continue;
             }
 
@@ -66,38 +83,29 @@ pub fn scan_string(input: String) -> Vec<Token> {
             }
 
             if kinda_special_char(c) {
-                if curr_str.chars().count() > 0
-                    && curr_str.chars().nth(0).unwrap() != ';'
-                    && curr_str.chars().nth(0).unwrap() != '"'
-                {
+                if curr_str.chars().count() > 0 {
                     let offset = if ci >= curr_str.chars().count() {
                         curr_str.chars().count()
                     } else {
                         0
                     };
 
-                    tokens.push(Token::new((li, li), (ci - offset, ci), curr_str));
-                    curr_str = String::new();
-                } else if curr_str.chars().count() > 0 && curr_str.chars().nth(0).unwrap() == ';' {
-                    curr_str.push(c);
-                    continue;
-                } else if curr_str.chars().count() > 0 && curr_str.chars().nth(0).unwrap() == '"' {
-                    let offset = if ci >= curr_str.chars().count() {
-                        curr_str.chars().count()
-                    } else {
-                        0
-                    };
-                    curr_str.push(c);
-                    tokens.push(Token::new((li,li), (ci - offset, ci), curr_str));
+                    tokens.push(Token::new((li, li), (ci - offset, ci - 1), curr_str));
                     curr_str = String::new();
-                } else {
-                    curr_str.push(c);
                 }
+                str_start = (li, ci);
+                curr_str.push(c);
                 continue;
             }
 
             curr_str.push(c);
         }
+
+        if curr_str.starts_with('"') {
+            curr_str.push('\n');
+            continue;
+        }
+
         if curr_str.chars().count() > 0 {
             tokens.push(Token::new(
                 (li, li),
@@ -107,15 +115,25 @@ pub fn scan_string(input: String) -> Vec<Token> {
             curr_str = String::new();
         }
     }
+
+    if curr_str.chars().count() > 0 {
+        let end = input.lines().count().saturating_sub(1);
+        tokens.push(Token::new((str_start.0, end), (str_start.1, str_start.1), curr_str));
+    }
     tokens
 }
 
+/// Whether the character following `prefix` is escaped by an odd run of backslashes.
+fn is_escaped(prefix: &str) -> bool {
+    prefix.chars().rev().take_while(|c| *c == '\\').count() % 2 == 1
+}
+
 fn kinda_special_char(c: char) -> bool {
     '\"' == c || ';' == c
 }
 
 fn is_special_char(c: &str, next_is_at: bool) -> bool {
-    "(" == c || ")" == c || (c == "," && !next_is_at) || (c == ",@")
+    "(" == c || ")" == c || "'" == c || "`" == c || (c == "," && !next_is_at) || (c == ",@")
 }
 
 #[cfg(test)]
@@ -194,6 +212,101 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_scan_string_literals() {
+        let types = |input: &str| scan_string(input.to_string())
+            .into_iter()
+            .map(|t| t.into_raw_parts())
+            .collect::<Vec<_>>();
+
+        assert_eq!(types("(echo \"a (b) c\")"), vec![
+            ((0, 0), (0, 0), TokenType::LeftParen),
+            ((0, 0), (1, 4), TokenType::Atom("echo".to_string())),
+            ((0, 0), (6, 14), TokenType::Str("\"a (b) c\"".to_string())),
+            ((0, 0), (15, 15), TokenType::RightParen),
+        ]);
+        assert_eq!(types("\"say \\\"hi\\\"\""), vec![
+            ((0, 0), (0, 11), TokenType::Str("\"say \\\"hi\\\"\"".to_string())),
+        ]);
+        assert_eq!(types("\"one\ntwo\""), vec![
+            ((0, 1), (0, 3), TokenType::Str("\"one\ntwo\"".to_string())),
+        ]);
+        assert_eq!(types("'(a) `b"), vec![
+            ((0, 0), (0, 0), TokenType::SingleQuote),
+            ((0, 0), (1, 1), TokenType::LeftParen),
+            ((0, 0), (2, 2), TokenType::Atom("a".to_string())),
+            ((0, 0), (3, 3), TokenType::RightParen),
+            ((0, 0), (5, 5), TokenType::Backquote),
+            ((0, 0), (6, 6), TokenType::Atom("b".to_string())),
+        ]);
+        assert_eq!(types("x1 ; a (comment)"), vec![
+            ((0, 0), (0, 1), TokenType::Atom("x1".to_string())),
+            ((0, 0), (3, 15), TokenType::Comment("; a (comment)".to_string())),
+        ]);
+    }
+
+    #[test]
+    fn test_scan_quotes() {
+        let types = |input: &str| scan_string(input.to_string())
+            .into_iter()
+            .map(|t| t.into_raw_parts().2)
+            .collect::<Vec<_>>();
+
+        assert_eq!(types("'a"), vec![TokenType::SingleQuote, TokenType::Atom("a".to_string())]);
+        assert_eq!(types("a'b"), vec![
+            TokenType::Atom("a".to_string()),
+            TokenType::SingleQuote,
+            TokenType::Atom("b".to_string()),
+        ]);
+        assert_eq!(types("`(a ,b ,@c)"), vec![
+            TokenType::Backquote,
+            TokenType::LeftParen,
+            TokenType::Atom("a".to_string()),
+            TokenType::Comma,
+            TokenType::Atom("b".to_string()),
+            TokenType::UnquoteSplice,
+            TokenType::Atom("c".to_string()),
+            TokenType::RightParen,
+        ]);
+        assert_eq!(types("\"it's `x`\""), vec![TokenType::Str("\"it's `x`\"".to_string())]);
+        assert_eq!(types("\"a;b\" \"c\\\\\" d"), vec![
+            TokenType::Str("\"a;b\"".to_string()),
+            TokenType::Str("\"c\\\\\"".to_string()),
+            TokenType::Atom("d".to_string()),
+        ]);
+        assert_eq!(types("; it's \"quoted\""), vec![TokenType::Comment("; it's \"quoted\"".to_string())]);
+    }
+
+    #[test]
+    fn test_scan_numbers() {
+        let types = |input: &str| scan_string(input.to_string())
+            .into_iter()
+            .map(|t| t.into_raw_parts().2)
+            .collect::<Vec<_>>();
+
+        assert_eq!(types("0 7 10 0123"), vec![
+            TokenType::Number(0),
+            TokenType::Number(7),
+            TokenType::Number(10),
+            TokenType::Number(123),
+        ]);
+        assert_eq!(types("a1 1a x10y -1"), vec![
+            TokenType::Atom("a1".to_string()),
+            TokenType::Atom("1a".to_string()),
+            TokenType::Atom("x10y".to_string()),
+            TokenType::Atom("-1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_is_quote_special() {
+        assert!(is_special_char("'", false));
+        assert!(is_special_char("'", true));
+
+        assert!(is_special_char("`", false));
+        assert!(is_special_char("`", true));
+    }
+
     #[test]
     fn test_is_special() {
         assert!(is_special_char("(", false));
@@ -1,3 +1,26 @@
-mod lexer;
-mod parser;
-mod errors;
+pub mod compiler;
+pub mod errors;
+#[allow(
+    dead_code,
+    clippy::collapsible_match,
+    clippy::explicit_counter_loop,
+    clippy::needless_bool,
+    clippy::redundant_field_names
+)]
+pub mod lexer;
+#[allow(
+    dead_code,
+    unused_imports,
+    clippy::explicit_counter_loop,
+    clippy::get_first,
+    clippy::new_without_default,
+    clippy::op_ref,
+    clippy::partialeq_to_none,
+    clippy::redundant_closure,
+    clippy::redundant_field_names
+)]
+pub mod parser;
+pub mod sh_ast;
+pub mod sh_parser;
+pub mod source_map;
+pub mod target;
@@ -1,3 +1,161 @@
+use shisp::compiler::{self, Options};
+use shisp::source_map::SourceMap;
+use shisp::target::{self, Target};
+use std::env;
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read};
+use std::process;
+
+const USAGE: &str = "\
+usage: shisp [options] <input.shisp | ->
+       shisp trace <script> [log]
+
+options:
+    -o <file>          write the script to <file> instead of stdout
+    --target <shell>   generate code for posix (the default), dash, bash, busybox or zsh
+    --report           list the features beyond POSIX sh the program needs
+    --source-map       end the script with a table mapping its lines to the source
+    --strip-comments   leave the comments of the source out of the script
+    --strict           start the script with `set -eu`
+    --keep-all         keep the functions the program never uses, for a library to be sourced
+    --notes            also show notes, such as recursive calls that could not become loops
+
+trace prefixes each line of <log> (or of stdin) that names a line of <script> with the Shisp
+position that line was generated from. This works for shell error messages and for traces
+whose PS4 holds the line number, as printed by
+    bash -c 'PS4=\"+ \\${LINENO}: \"; set -x; . ./script.sh' 2>&1 | shisp trace script.sh
+The script must have been compiled with --source-map.";
+
+struct Args {
+    input: String,
+    output: Option<String>,
+    report: bool,
+    source_map: bool,
+    notes: bool,
+    options: Options,
+}
+
+fn parse_args(mut args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut input = None;
+    let mut output = None;
+    let mut report = false;
+    let mut source_map = false;
+    let mut notes = false;
+    let mut options = Options::default();
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-o" => output = Some(args.next().ok_or("`-o` expects a file name")?),
+            "--target" => {
+                let name = args.next().ok_or("`--target` expects a shell")?;
+                options.target = Target::from_name(&name).ok_or(format!("unknown target `{}`", name))?;
+            }
+            "--report" => report = true,
+            "--source-map" => source_map = true,
+            "--strip-comments" => options.strip_comments = true,
+            "--notes" => notes = true,
+            "--strict" => options.strict = true,
+            "--keep-all" => options.keep_all = true,
+            "-h" | "--help" => return Err(String::new()),
+            _ if arg.starts_with('-') && arg != "-" => return Err(format!("unknown option `{}`", arg)),
+            _ if input.is_some() => return Err("only one input file can be given".to_string()),
+            _ => input = Some(arg),
+        }
+    }
+
+    Ok(Args {
+        input: input.ok_or("no input file given")?,
+        output,
+        report,
+        source_map,
+        notes,
+        options,
+    })
+}
+
+fn read_input(path: &str) -> io::Result<String> {
+    if path == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source)?;
+        Ok(source)
+    } else {
+        fs::read_to_string(path)
+    }
+}
+
+fn fail(msg: String) -> ! {
+    eprintln!("shisp: {}", msg);
+    process::exit(1);
+}
+
+fn trace(script_path: &str, log: Option<&str>) {
+    let script = fs::read_to_string(script_path)
+        .unwrap_or_else(|e| fail(format!("cannot read {}: {}", script_path, e)));
+    let (source, map) = SourceMap::from_script(&script)
+        .unwrap_or_else(|| fail(format!("{} has no source map; compile it with --source-map", script_path)));
+
+    let log: Box<dyn BufRead> = match log {
+        Some(path) => Box::new(BufReader::new(
+            fs::File::open(path).unwrap_or_else(|e| fail(format!("cannot read {}: {}", path, e))),
+        )),
+        None => Box::new(BufReader::new(io::stdin())),
+    };
+    for line in log.lines() {
+        let line = line.unwrap_or_else(|e| fail(format!("cannot read the log: {}", e)));
+        println!("{}", map.trace(&source, &line));
+    }
+}
+
 fn main() {
-    println!("Hello, world!");
+    let argv = env::args().skip(1).collect::<Vec<String>>();
+    if argv.first().map(String::as_str) == Some("trace") {
+        match &argv[1..] {
+            [script] => trace(script, None),
+            [script, log] => trace(script, Some(log)),
+            _ => {
+                eprintln!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+        return;
+    }
+
+    let args = match parse_args(argv.into_iter()) {
+        Ok(args) => args,
+        Err(msg) if msg.is_empty() => {
+            println!("{}", USAGE);
+            return;
+        }
+        Err(msg) => {
+            eprintln!("shisp: {}\n{}", msg, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let source = read_input(&args.input)
+        .unwrap_or_else(|e| fail(format!("cannot read {}: {}", args.input, e)));
+
+    let output = compiler::compile_with(source, &args.options).unwrap_or_else(|e| {
+        eprintln!("{}:{}", args.input, e);
+        process::exit(1);
+    });
+    for warning in output.warnings.iter().filter(|w| args.notes || !w.is_note()) {
+        eprintln!("{}:{}", args.input, warning);
+    }
+    if args.notes && !output.removed.is_empty() {
+        eprintln!("{}: note: left out the unused runtime functions {}", args.input, output.removed.join(", "));
+    }
+    if args.report {
+        eprint!("{}", target::report(&output.features));
+    }
+
+    let mut script = output.script;
+    if args.source_map {
+        script.push_str(&output.source_map.to_comments(&args.input));
+    }
+    match &args.output {
+        Some(path) => fs::write(path, &script)
+            .unwrap_or_else(|e| fail(format!("cannot write {}: {}", path, e))),
+        None => print!("{}", script),
+    }
 }
@@ -1,4 +1,5 @@
 pub mod ast;
+pub mod form;
 
 use crate::lexer::tokens::Token;
 use self::ast::{AST, Node, NodeType};
@@ -78,6 +78,10 @@ impl AST {
             .collect()
     }
 
+    pub fn get_node(&self, node_index: usize) -> Option<&Node> {
+        self.nodes.get(node_index)
+    }
+
     pub fn get_children(&self, node: &Node) -> Vec<&Node> {
         if self.nodes.contains(node) {
             let index = self.nodes
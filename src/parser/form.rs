@@ -0,0 +1,228 @@
+use super::ast::{AST, Node, NodeType};
+use crate::errors::{ShispError, ShispErrorType};
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum FormType {
+    List(Vec<Form>),
+    Atom(String),
+    Str(String),
+    Number(u128),
+    Boolean(bool),
+
+    Quote(Box<Form>),
+    Quasiquote(Box<Form>),
+    Unquote(Box<Form>),
+    UnquoteSplice(Box<Form>),
+}
+
+/// A nested view of the `AST`, with the reader prefixes folded into the form they apply to and
+/// comments attached to the form following them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Form {
+    pub row: (usize, usize),
+    pub col: (usize, usize),
+
+    pub form_type: FormType,
+    /// The text of the comment lines right before this form, without their leading `;`.
+    pub comments: Vec<String>,
+}
+
+impl Form {
+    pub fn from_ast(ast: &AST) -> Result<Vec<Form>, ShispError> {
+        let mut base_nodes = ast.get_base_node_indexes().into_iter().collect::<Vec<usize>>();
+        base_nodes.sort();
+        read_sequence(ast, &base_nodes)
+    }
+
+    pub fn new(form_type: FormType) -> Form {
+        Form {
+            row: (0, 0),
+            col: (0, 0),
+            form_type,
+            comments: vec![],
+        }
+    }
+
+    pub fn as_atom(&self) -> Option<&str> {
+        match &self.form_type {
+            FormType::Atom(name) => Some(name),
+            _ => None,
+        }
+    }
+
+    pub fn as_list(&self) -> Option<&[Form]> {
+        match &self.form_type {
+            FormType::List(forms) => Some(forms),
+            _ => None,
+        }
+    }
+
+    /// The name of the operator if this form is a list headed by an atom.
+    pub fn head(&self) -> Option<&str> {
+        self.as_list()?.first()?.as_atom()
+    }
+
+    pub fn error(&self, error_type: ShispErrorType) -> ShispError {
+        ShispError::at(error_type, self.row, self.col)
+    }
+}
+
+fn read_sequence(ast: &AST, indexes: &[usize]) -> Result<Vec<Form>, ShispError> {
+    let mut forms = vec![];
+    let mut indexes = indexes.iter();
+    while let Some(form) = read_form(ast, &mut indexes)? {
+        forms.push(form);
+    }
+    Ok(forms)
+}
+
+fn read_form<'a, I>(ast: &AST, indexes: &mut I) -> Result<Option<Form>, ShispError>
+where
+    I: Iterator<Item = &'a usize>,
+{
+    let mut comments = vec![];
+    while let Some(index) = indexes.next() {
+        let node = ast.get_node(*index).unwrap();
+        let form_type = match &node.node_type {
+            NodeType::Comment(text) => {
+                comments.push(comment_text(text));
+                continue;
+            }
+            NodeType::CloseExpr => continue,
+            NodeType::Expr => FormType::List(read_sequence(ast, &ast.get_children_index(*index))?),
+            NodeType::Str(raw) => FormType::Str(unescape(raw)),
+            NodeType::Atom(name) => FormType::Atom(name.clone()),
+            NodeType::Number(n) => FormType::Number(*n),
+            NodeType::Boolean(b) => FormType::Boolean(*b),
+            NodeType::Quote => FormType::Quote(read_prefixed(ast, indexes, node)?),
+            NodeType::Quasiquote => FormType::Quasiquote(read_prefixed(ast, indexes, node)?),
+            NodeType::Unquote => FormType::Unquote(read_prefixed(ast, indexes, node)?),
+            NodeType::UnquoteSplice => FormType::UnquoteSplice(read_prefixed(ast, indexes, node)?),
+        };
+
+        return Ok(Some(Form {
+            row: node.row,
+            col: node.col,
+            form_type,
+            comments,
+        }));
+    }
+    Ok(None)
+}
+
+fn read_prefixed<'a, I>(ast: &AST, indexes: &mut I, prefix: &Node) -> Result<Box<Form>, ShispError>
+where
+    I: Iterator<Item = &'a usize>,
+{
+    match read_form(ast, indexes)? {
+        Some(form) => Ok(Box::new(form)),
+        None => Err(ShispError::at(ShispErrorType::EOF, prefix.row, prefix.col)),
+    }
+}
+
+/// Strips the `;`s a comment starts with, and the space after them.
+fn comment_text(raw: &str) -> String {
+    let text = raw.trim_end().trim_start_matches(';');
+    text.strip_prefix(' ').unwrap_or(text).to_string()
+}
+
+/// Strips the surrounding quotes from a string token and resolves its escape sequences.
+fn unescape(raw: &str) -> String {
+    let inner = raw
+        .strip_prefix('"')
+        .map(|s| s.strip_suffix('"').unwrap_or(s))
+        .unwrap_or(raw);
+
+    let mut out = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some(other) => {
+                out.push('\\');
+                out.push(other);
+            }
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::parser::parse_tokens;
+
+    fn read(input: &str) -> Vec<Form> {
+        Form::from_ast(&parse_tokens(lexer::scan_string(input.to_string()))).unwrap()
+    }
+
+    fn strip(form: Form) -> FormType {
+        match form.form_type {
+            FormType::List(forms) => FormType::List(forms.into_iter().map(|f| Form::new(strip(f))).collect()),
+            FormType::Quote(f) => FormType::Quote(Box::new(Form::new(strip(*f)))),
+            FormType::Unquote(f) => FormType::Unquote(Box::new(Form::new(strip(*f)))),
+            other => other,
+        }
+    }
+
+    fn atom(name: &str) -> Form {
+        Form::new(FormType::Atom(name.to_string()))
+    }
+
+    #[test]
+    fn from_ast() {
+        let forms = read("(define x 1) ;note\n(echo \"a b\" #t)")
+            .into_iter()
+            .map(strip)
+            .collect::<Vec<FormType>>();
+
+        assert_eq!(forms, vec![
+            FormType::List(vec![atom("define"), atom("x"), Form::new(FormType::Number(1))]),
+            FormType::List(vec![
+                atom("echo"),
+                Form::new(FormType::Str("a b".to_string())),
+                Form::new(FormType::Boolean(true)),
+            ]),
+        ]);
+    }
+
+    #[test]
+    fn prefixes() {
+        let forms = read("'(a) (f ,x)").into_iter().map(strip).collect::<Vec<FormType>>();
+
+        assert_eq!(forms, vec![
+            FormType::Quote(Box::new(Form::new(FormType::List(vec![atom("a")])))),
+            FormType::List(vec![atom("f"), Form::new(FormType::Unquote(Box::new(atom("x"))))]),
+        ]);
+    }
+
+    #[test]
+    fn comments() {
+        let forms = read(";; Settings\n;\n(define x 1) ; trailing\n(f\n  ;;inner\n  x)");
+        assert_eq!(forms[0].comments, vec!["Settings".to_string(), "".to_string()]);
+        assert_eq!(forms[1].comments, vec!["trailing".to_string()]);
+        assert_eq!(forms[1].as_list().unwrap()[1].comments, vec!["inner".to_string()]);
+    }
+
+    #[test]
+    fn dangling_prefix() {
+        let ast = parse_tokens(lexer::scan_string("(a ,)".to_string()));
+        assert_eq!(Form::from_ast(&ast).unwrap_err().error_type(), &ShispErrorType::EOF);
+    }
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("\"a\\nb\""), "a\nb");
+        assert_eq!(unescape("\"say \\\"hi\\\"\""), "say \"hi\"");
+        assert_eq!(unescape("\"\\$HOME\""), "\\$HOME");
+    }
+}
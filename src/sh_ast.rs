@@ -0,0 +1,220 @@
+//! A typed representation of the sh code the compiler generates. The code generator builds these
+//! nodes and `printer` turns them into consistently indented POSIX sh, so quoting and nesting are
+//! decided in one place.
+
+pub mod printer;
+
+/// A single sh word, made of parts that are each quoted according to what they are.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Word(pub Vec<WordPart>);
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum WordPart {
+    /// Text taken literally, quoted when needed.
+    Literal(String),
+    /// The value of a variable, always double quoted.
+    Var(String),
+    /// A `${...}` parameter expansion, always double quoted.
+    Param(String),
+    /// A `$(...)` command substitution, always double quoted.
+    CommandSubst(Vec<Command>),
+    /// Text quoted as a `$'...'` string, which not every shell understands.
+    DollarQuoted(String),
+    /// A `$((...))` arithmetic expansion.
+    Arith(String),
+    /// Text printed exactly as given.
+    Raw(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum RedirectOp {
+    Input,
+    Output,
+    Append,
+    DupInput,
+    DupOutput,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Redirect {
+    pub fd: Option<u32>,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AndOrOp {
+    And,
+    Or,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct CaseArm {
+    pub patterns: Vec<Word>,
+    pub body: Vec<Command>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Command {
+    Simple {
+        assignments: Vec<(String, Word)>,
+        words: Vec<Word>,
+        redirects: Vec<Redirect>,
+    },
+    Pipeline {
+        negated: bool,
+        commands: Vec<Command>,
+    },
+    AndOr {
+        first: Box<Command>,
+        rest: Vec<(AndOrOp, Command)>,
+    },
+    If {
+        condition: Vec<Command>,
+        then_body: Vec<Command>,
+        else_body: Option<Vec<Command>>,
+    },
+    While {
+        until: bool,
+        condition: Vec<Command>,
+        body: Vec<Command>,
+    },
+    For {
+        var: String,
+        words: Vec<Word>,
+        body: Vec<Command>,
+    },
+    Case {
+        word: Word,
+        arms: Vec<CaseArm>,
+    },
+    /// `command` reading a here-document, whose `body` is empty or ends in a newline. The body is
+    /// taken literally when `quoted`, and expanded like a double-quoted string otherwise.
+    HereDoc {
+        command: Box<Command>,
+        delimiter: String,
+        quoted: bool,
+        body: String,
+    },
+    BraceGroup(Vec<Command>),
+    Subshell(Vec<Command>),
+    FunctionDef {
+        name: String,
+        body: Vec<Command>,
+    },
+    Redirected {
+        command: Box<Command>,
+        redirects: Vec<Redirect>,
+    },
+    Background(Box<Command>),
+    /// A command generated from the form at `position` (row and column), recorded in the source map.
+    Sourced {
+        position: (usize, usize),
+        command: Box<Command>,
+    },
+    Comment(String),
+    /// Code printed exactly as given, such as a `shell-literal` or the runtime.
+    Raw(String),
+}
+
+impl Word {
+    pub fn literal(s: &str) -> Word {
+        Word(vec![WordPart::Literal(s.to_string())])
+    }
+
+    pub fn var(name: &str) -> Word {
+        Word(vec![WordPart::Var(name.to_string())])
+    }
+
+    pub fn raw(s: &str) -> Word {
+        Word(vec![WordPart::Raw(s.to_string())])
+    }
+
+    /// The variable this word expands to, if it is nothing but a variable.
+    pub fn as_var(&self) -> Option<&str> {
+        match self.0.as_slice() {
+            [WordPart::Var(name)] => Some(name),
+            _ => None,
+        }
+    }
+}
+
+impl Command {
+    pub fn simple(words: Vec<Word>) -> Command {
+        Command::Simple {
+            assignments: vec![],
+            words,
+            redirects: vec![],
+        }
+    }
+
+    pub fn assign(name: &str, value: Word) -> Command {
+        Command::Simple {
+            assignments: vec![(name.to_string(), value)],
+            words: vec![],
+            redirects: vec![],
+        }
+    }
+
+    /// Runs `name` with literal `args`, a shorthand for calls to the runtime.
+    pub fn call(name: &str, args: Vec<Word>) -> Command {
+        let mut words = vec![Word::literal(name)];
+        words.extend(args);
+        Command::simple(words)
+    }
+
+    /// Runs `commands` as one command, grouping them with `{ }` if there is more than one.
+    pub fn group(mut commands: Vec<Command>) -> Command {
+        match commands.len() {
+            0 => Command::simple(vec![Word::raw(":")]),
+            1 => commands.remove(0),
+            _ => Command::BraceGroup(commands),
+        }
+    }
+
+    /// Applies `redirect` to the whole of this command. Pipelines and lists are grouped first, and
+    /// the redirection goes before those already on the command so that theirs take precedence.
+    pub fn redirect(self, redirect: Redirect) -> Command {
+        match self {
+            Command::Sourced { position, command } => Command::Sourced {
+                position,
+                command: Box::new(command.redirect(redirect)),
+            },
+            Command::Redirected { command, mut redirects } => {
+                redirects.insert(0, redirect);
+                Command::Redirected { command, redirects }
+            }
+            Command::HereDoc { command, delimiter, quoted, body } => Command::HereDoc {
+                command: Box::new(command.redirect(redirect)),
+                delimiter,
+                quoted,
+                body,
+            },
+            command @ (Command::Pipeline { .. } | Command::AndOr { .. } | Command::Background(_)) => {
+                Command::BraceGroup(vec![command]).redirect(redirect)
+            }
+            command => Command::Redirected {
+                command: Box::new(command),
+                redirects: vec![redirect],
+            },
+        }
+    }
+
+    /// The command a `Sourced` command wraps, or this command itself.
+    pub fn unwrap_sourced(&self) -> &Command {
+        match self {
+            Command::Sourced { command, .. } => command.unwrap_sourced(),
+            command => command,
+        }
+    }
+
+    /// `[ -n word ]`, the test for a true Shisp value.
+    pub fn test_true(word: Word) -> Command {
+        Command::simple(vec![
+            Word::raw("["),
+            Word::literal("-n"),
+            word,
+            Word::raw("]"),
+        ])
+    }
+}
@@ -0,0 +1,585 @@
+use super::{AndOrOp, CaseArm, Command, Redirect, RedirectOp, Word, WordPart};
+
+const INDENT: &str = "    ";
+
+const RESERVED: [&str; 14] = [
+    "if", "then", "else", "elif", "fi", "do", "done", "case", "esac",
+    "while", "until", "for", "in", "function",
+];
+
+/// Prints `commands` as a POSIX sh program, one command per line, with nested bodies indented
+/// by four spaces.
+pub fn print(commands: &[Command]) -> String {
+    print_mapped(commands).0
+}
+
+/// Prints a word so that sh reads it back as exactly one word.
+pub fn print_word(word: &Word) -> String {
+    if word.0.is_empty() {
+        return "''".to_string();
+    }
+    word.0.iter().map(print_part).collect()
+}
+
+fn print_part(part: &WordPart) -> String {
+    match part {
+        WordPart::Literal(s) => quote(s),
+        WordPart::Var(name) => format!("\"${}\"", name),
+        WordPart::Param(expr) => format!("\"${{{}}}\"", expr),
+        WordPart::CommandSubst(commands) => {
+            let heredocs = commands.iter().map(heredocs).collect::<String>();
+            let end = if heredocs.is_empty() { "" } else { "\n" };
+            format!("\"$({}{}{})\"", inline_list(commands), heredocs, end)
+        }
+        WordPart::DollarQuoted(s) => dollar_quote(s),
+        WordPart::Arith(expr) => format!("$(({}))", expr),
+        WordPart::Raw(s) => s.clone(),
+    }
+}
+
+/// Quotes `s` for sh, leaving it bare when that is unambiguous.
+pub fn quote(s: &str) -> String {
+    let bare = !s.is_empty()
+        && !RESERVED.contains(&s)
+        && s.chars().all(|c| c.is_ascii_alphanumeric() || "_./:,+@%-".contains(c));
+    if bare {
+        s.to_string()
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Quotes `s` as a `$'...'` string, spelling control characters as escapes.
+pub fn dollar_quote(s: &str) -> String {
+    let mut out = String::from("$'");
+    for c in s.chars() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '\'' => out.push_str("\\'"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_ascii_control() => out.push_str(&format!("\\x{:02x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('\'');
+    out
+}
+
+/// Prints `commands` like `print`, also returning the source position each output line was
+/// generated from, as given by the innermost `Sourced` command around it.
+pub fn print_mapped(commands: &[Command]) -> (String, Vec<Option<(usize, usize)>>) {
+    let mut printer = Printer {
+        out: String::new(),
+        positions: vec![],
+    };
+    printer.commands(commands, 0);
+    (printer.out, printer.positions)
+}
+
+struct Printer {
+    out: String,
+    positions: Vec<Option<(usize, usize)>>,
+}
+
+impl Printer {
+    fn line(&mut self, indent: usize, text: &str) {
+        self.out.push_str(&INDENT.repeat(indent));
+        self.out.push_str(text);
+        self.out.push('\n');
+        self.positions.extend(text.split('\n').map(|_| None));
+    }
+
+    fn commands(&mut self, commands: &[Command], indent: usize) {
+        if commands.is_empty() {
+            self.line(indent, ":");
+        }
+        for command in commands {
+            self.command(command, indent);
+        }
+    }
+
+    fn command(&mut self, command: &Command, indent: usize) {
+        match command {
+            Command::If { .. } => self.if_command(command, indent, "if"),
+            Command::While { until, condition, body } => {
+                self.header(indent, if *until { "until" } else { "while" }, condition, "do");
+                self.commands(body, indent + 1);
+                self.line(indent, "done");
+            }
+            Command::For { var, words, body } => {
+                let words = words.iter().map(print_word).collect::<Vec<String>>();
+                self.line(indent, &format!("for {} in {}; do", var, words.join(" ")));
+                self.commands(body, indent + 1);
+                self.line(indent, "done");
+            }
+            Command::Case { word, arms } => {
+                self.line(indent, &format!("case {} in", print_word(word)));
+                for arm in arms {
+                    self.line(indent + 1, &format!("{})", print_patterns(arm)));
+                    self.commands(&arm.body, indent + 2);
+                    self.line(indent + 2, ";;");
+                }
+                self.line(indent, "esac");
+            }
+            Command::BraceGroup(body) => {
+                self.line(indent, "{");
+                self.commands(body, indent + 1);
+                self.line(indent, "}");
+            }
+            Command::Subshell(body) => {
+                self.line(indent, "(");
+                self.commands(body, indent + 1);
+                self.line(indent, ")");
+            }
+            Command::FunctionDef { name, body } => {
+                self.line(indent, &format!("{}() {{", name));
+                self.commands(body, indent + 1);
+                self.line(indent, "}");
+            }
+            Command::Pipeline { negated, commands } if commands.last().is_some_and(|c| !is_inline(c)) => {
+                // Only the last stage is spread over lines; the others go in front of its first line.
+                let (last, stages) = commands.split_last().unwrap();
+                let mut prefix = stages.iter().map(|c| inline(c) + " | ").collect::<String>();
+                if *negated {
+                    prefix.insert_str(0, "! ");
+                }
+                let start = self.out.len() + INDENT.len() * indent;
+                self.command(last, indent);
+                self.out.insert_str(start, &prefix);
+            }
+            Command::Redirected { command, redirects } if heredocs(command).is_empty() => {
+                self.command(command, indent);
+                self.out.pop();
+                self.out.push_str(&format!(" {}\n", print_redirects(redirects)));
+            }
+            Command::Sourced { position, command } => {
+                let start = self.positions.len();
+                self.command(command, indent);
+                for line in &mut self.positions[start..] {
+                    line.get_or_insert(*position);
+                }
+            }
+            Command::Comment(text) => {
+                for comment_line in text.split('\n') {
+                    self.line(indent, format!("# {}", comment_line).trim_end());
+                }
+            }
+            Command::Raw(text) => self.line(indent, text.trim_end_matches('\n')),
+            _ => self.line(indent, &format!("{}{}", inline(command), heredocs(command))),
+        }
+    }
+
+    /// Prints an `if`, turning an `else` holding nothing but another `if` into an `elif`.
+    fn if_command(&mut self, command: &Command, indent: usize, keyword: &str) {
+        if let Command::If { condition, then_body, else_body } = command {
+            self.header(indent, keyword, condition, "then");
+            self.commands(then_body, indent + 1);
+            match else_body.as_deref() {
+                Some([nested @ Command::If { .. }]) => return self.if_command(nested, indent, "elif"),
+                Some(body) => {
+                    self.line(indent, "else");
+                    self.commands(body, indent + 1);
+                }
+                None => {}
+            }
+            self.line(indent, "fi");
+        }
+    }
+
+    /// Prints `keyword condition; then`, spreading the condition over several lines when it has
+    /// more than one command.
+    fn header(&mut self, indent: usize, keyword: &str, condition: &[Command], then: &str) {
+        match condition {
+            [single] if is_inline(single) && !inline(single).contains('\n') => {
+                self.line(indent, &format!("{} {}; {}{}", keyword, inline(single), then, heredocs(single)));
+            }
+            _ => {
+                self.line(indent, keyword);
+                self.commands(condition, indent + 1);
+                self.line(indent, then);
+            }
+        }
+    }
+}
+
+/// Whether `command` reads well on one line, as `if` and `while` conditions should.
+fn is_inline(command: &Command) -> bool {
+    match command.unwrap_sourced() {
+        Command::Simple { .. } | Command::Pipeline { .. } | Command::AndOr { .. } | Command::Background(_) => true,
+        Command::HereDoc { command, .. } => is_inline(command),
+        Command::Redirected { command, .. } => match command.unwrap_sourced() {
+            Command::BraceGroup(body) => body.iter().all(is_inline),
+            command => is_inline(command),
+        },
+        _ => false,
+    }
+}
+
+fn print_patterns(arm: &CaseArm) -> String {
+    arm.patterns.iter().map(print_word).collect::<Vec<String>>().join("|")
+}
+
+fn print_redirects(redirects: &[Redirect]) -> String {
+    redirects.iter().map(print_redirect).collect::<Vec<String>>().join(" ")
+}
+
+fn print_redirect(redirect: &Redirect) -> String {
+    let fd = redirect.fd.map(|fd| fd.to_string()).unwrap_or_default();
+    let target = print_word(&redirect.target);
+    match redirect.op {
+        RedirectOp::Input => format!("{}< {}", fd, target),
+        RedirectOp::Output => format!("{}> {}", fd, target),
+        RedirectOp::Append => format!("{}>> {}", fd, target),
+        RedirectOp::DupInput => format!("{}<&{}", fd, target),
+        RedirectOp::DupOutput => format!("{}>&{}", fd, target),
+    }
+}
+
+/// Prints a command on a single line, as needed inside pipelines and substitutions.
+fn inline(command: &Command) -> String {
+    match command {
+        Command::Simple { assignments, words, redirects } => {
+            let mut parts = assignments
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, print_word(value)))
+                .collect::<Vec<String>>();
+            parts.extend(words.iter().map(print_word));
+            if !redirects.is_empty() {
+                parts.push(print_redirects(redirects));
+            }
+            if parts.is_empty() {
+                ":".to_string()
+            } else {
+                parts.join(" ")
+            }
+        }
+        Command::Pipeline { negated, commands } => {
+            let pipeline = commands.iter().map(inline).collect::<Vec<String>>().join(" | ");
+            if *negated {
+                format!("! {}", pipeline)
+            } else {
+                pipeline
+            }
+        }
+        Command::AndOr { first, rest } => {
+            let mut out = inline(first);
+            for (op, command) in rest {
+                out.push_str(match op {
+                    AndOrOp::And => " && ",
+                    AndOrOp::Or => " || ",
+                });
+                out.push_str(&inline(command));
+            }
+            out
+        }
+        Command::Background(command) => format!("{} &", inline(command)),
+        Command::If { condition, then_body, else_body } => {
+            let mut out = format!("if {}; then {}", inline_list(condition), inline_list(then_body));
+            if let Some(else_body) = else_body {
+                out.push_str(&format!("; else {}", inline_list(else_body)));
+            }
+            out + "; fi"
+        }
+        Command::While { until, condition, body } => format!(
+            "{} {}; do {}; done",
+            if *until { "until" } else { "while" },
+            inline_list(condition),
+            inline_list(body)
+        ),
+        Command::For { var, words, body } => format!(
+            "for {} in {}; do {}; done",
+            var,
+            words.iter().map(print_word).collect::<Vec<String>>().join(" "),
+            inline_list(body)
+        ),
+        Command::Case { word, arms } => {
+            let arms = arms
+                .iter()
+                .map(|arm| format!("{}) {};;", print_patterns(arm), inline_list(&arm.body)))
+                .collect::<Vec<String>>();
+            format!("case {} in {} esac", print_word(word), arms.join(" "))
+        }
+        Command::HereDoc { command, delimiter, quoted, .. } => {
+            format!("{} <<{}", inline(command), if *quoted { format!("'{}'", delimiter) } else { delimiter.clone() })
+        }
+        Command::BraceGroup(body) => format!("{{ {}; }}", inline_list(body)),
+        Command::Subshell(body) => format!("({})", inline_list(body)),
+        Command::FunctionDef { name, body } => format!("{}() {{ {}; }}", name, inline_list(body)),
+        Command::Redirected { command, redirects } => {
+            format!("{} {}", inline(command), print_redirects(redirects))
+        }
+        Command::Sourced { command, .. } => inline(command),
+        Command::Comment(_) => ":".to_string(),
+        Command::Raw(text) => text.trim_end_matches('\n').to_string(),
+    }
+}
+
+/// The here-documents of the commands in `inline(command)`, each starting on a new line, to print
+/// after the line holding their `<<` operators. Those in command substitutions are printed there.
+fn heredocs(command: &Command) -> String {
+    let all = |commands: &[Command]| commands.iter().map(heredocs).collect::<String>();
+    match command {
+        Command::HereDoc { command, delimiter, body, .. } => format!("{}\n{}{}", heredocs(command), body, delimiter),
+        Command::Pipeline { commands, .. } => all(commands),
+        Command::AndOr { first, rest } => heredocs(first) + &rest.iter().map(|(_, c)| heredocs(c)).collect::<String>(),
+        Command::Background(command) | Command::Redirected { command, .. } | Command::Sourced { command, .. } => {
+            heredocs(command)
+        }
+        Command::If { condition, then_body, else_body } => {
+            all(condition) + &all(then_body) + &else_body.as_deref().map(all).unwrap_or_default()
+        }
+        Command::While { condition, body, .. } => all(condition) + &all(body),
+        Command::For { body, .. }
+        | Command::BraceGroup(body)
+        | Command::Subshell(body)
+        | Command::FunctionDef { body, .. } => all(body),
+        Command::Case { arms, .. } => arms.iter().map(|arm| all(&arm.body)).collect(),
+        Command::Simple { .. } | Command::Comment(_) | Command::Raw(_) => String::new(),
+    }
+}
+
+/// Joins commands with `; `, or with newlines when one of them is raw text that could end in a
+/// comment. Comments themselves are left out.
+fn inline_list(commands: &[Command]) -> String {
+    let separator = if commands.iter().any(|c| matches!(c.unwrap_sourced(), Command::Raw(_))) {
+        "\n"
+    } else {
+        "; "
+    };
+    let commands = commands
+        .iter()
+        .filter(|c| !matches!(c.unwrap_sourced(), Command::Comment(_)))
+        .map(inline)
+        .collect::<Vec<String>>();
+    if commands.is_empty() {
+        return ":".to_string();
+    }
+    commands.join(separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(s: &str) -> Command {
+        Command::call("echo", vec![Word::literal(s)])
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain-word.txt"), "plain-word.txt");
+        assert_eq!(quote("two words"), "'two words'");
+        assert_eq!(quote("it's"), "'it'\\''s'");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("#t"), "'#t'");
+        assert_eq!(quote("fi"), "'fi'");
+        assert_eq!(dollar_quote("a\tb'c\\\n\x07"), "$'a\\tb\\'c\\\\\\n\\x07'");
+    }
+
+    #[test]
+    fn words() {
+        let word = Word(vec![
+            WordPart::Literal("dir/".to_string()),
+            WordPart::Var("s_name".to_string()),
+            WordPart::Literal(" copy".to_string()),
+        ]);
+        assert_eq!(print_word(&word), "dir/\"$s_name\"' copy'");
+        assert_eq!(print_word(&Word(vec![])), "''");
+
+        let subst = Word(vec![WordPart::CommandSubst(vec![Command::Pipeline {
+            negated: false,
+            commands: vec![echo("a"), Command::call("tr", vec![Word::literal("a"), Word::literal("b")])],
+        }])]);
+        assert_eq!(print_word(&subst), "\"$(echo a | tr a b)\"");
+    }
+
+    #[test]
+    fn nested_ifs_become_elif() {
+        let program = vec![Command::If {
+            condition: vec![Command::test_true(Word::var("a"))],
+            then_body: vec![echo("a")],
+            else_body: Some(vec![Command::If {
+                condition: vec![echo("probe"), Command::test_true(Word::var("b"))],
+                then_body: vec![echo("b")],
+                else_body: Some(vec![echo("c")]),
+            }]),
+        }];
+
+        assert_eq!(print(&program), [
+            "if [ -n \"$a\" ]; then",
+            "    echo a",
+            "elif",
+            "    echo probe",
+            "    [ -n \"$b\" ]",
+            "then",
+            "    echo b",
+            "else",
+            "    echo c",
+            "fi",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn compound_commands() {
+        let program = vec![
+            Command::FunctionDef {
+                name: "f".to_string(),
+                body: vec![Command::Redirected {
+                    command: Box::new(Command::While {
+                        until: false,
+                        condition: vec![Command::call("read", vec![Word::literal("-r"), Word::literal("line")])],
+                        body: vec![],
+                    }),
+                    redirects: vec![Redirect {
+                        fd: None,
+                        op: RedirectOp::Input,
+                        target: Word::literal("my file"),
+                    }],
+                }],
+            },
+            Command::Case {
+                word: Word::var("x"),
+                arms: vec![CaseArm {
+                    patterns: vec![Word::raw("*.txt"), Word::literal("a b")],
+                    body: vec![echo("text")],
+                }],
+            },
+            Command::AndOr {
+                first: Box::new(echo("a")),
+                rest: vec![(AndOrOp::Or, Command::Background(Box::new(echo("b"))))],
+            },
+            Command::Pipeline {
+                negated: false,
+                commands: vec![echo("c"), Command::While {
+                    until: false,
+                    condition: vec![Command::call("read", vec![Word::literal("line")])],
+                    body: vec![echo("d")],
+                }],
+            },
+        ];
+
+        assert_eq!(print(&program), [
+            "f() {",
+            "    while read -r line; do",
+            "        :",
+            "    done < 'my file'",
+            "}",
+            "case \"$x\" in",
+            "    *.txt|'a b')",
+            "        echo text",
+            "        ;;",
+            "esac",
+            "echo a || echo b &",
+            "echo c | while read line; do",
+            "    echo d",
+            "done",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn loops_and_negations() {
+        let for_loop = |var: &str| Command::For {
+            var: var.to_string(),
+            words: vec![Word::raw("*.txt"), Word::literal("a b")],
+            body: vec![Command::call("echo", vec![Word::var(var)])],
+        };
+        let until_loop = Command::While {
+            until: true,
+            condition: vec![Command::call("test", vec![Word::literal("-f"), Word::literal("ready")])],
+            body: vec![Command::call("sleep", vec![Word::literal("1")])],
+        };
+        let program = vec![
+            for_loop("f"),
+            until_loop.clone(),
+            Command::Pipeline {
+                negated: true,
+                commands: vec![echo("a"), Command::call("grep", vec![Word::literal("b")])],
+            },
+            Command::Pipeline {
+                negated: true,
+                commands: vec![echo("c"), Command::While {
+                    until: false,
+                    condition: vec![Command::call("read", vec![Word::literal("line")])],
+                    body: vec![echo("d")],
+                }],
+            },
+            Command::Redirected {
+                command: Box::new(Command::call("read", vec![Word::literal("line")])),
+                redirects: vec![Redirect {
+                    fd: Some(0),
+                    op: RedirectOp::DupInput,
+                    target: Word::raw("3"),
+                }],
+            },
+            Command::call("echo", vec![Word(vec![WordPart::CommandSubst(vec![for_loop("g"), until_loop])])]),
+        ];
+
+        assert_eq!(print(&program), [
+            "for f in *.txt 'a b'; do",
+            "    echo \"$f\"",
+            "done",
+            "until test -f ready; do",
+            "    sleep 1",
+            "done",
+            "! echo a | grep b",
+            "! echo c | while read line; do",
+            "    echo d",
+            "done",
+            "read line 0<&3",
+            "echo \"$(for g in *.txt 'a b'; do echo \"$g\"; done; until test -f ready; do sleep 1; done)\"",
+            "",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn heredocs() {
+        let heredoc = |quoted: bool| Command::HereDoc {
+            command: Box::new(Command::call("cat", vec![])),
+            delimiter: "EOF".to_string(),
+            quoted,
+            body: "$x\n".to_string(),
+        };
+        let program = vec![
+            heredoc(true).redirect(Redirect {
+                fd: None,
+                op: RedirectOp::Output,
+                target: Word::literal("f"),
+            }),
+            Command::FunctionDef {
+                name: "f".to_string(),
+                body: vec![Command::If {
+                    condition: vec![heredoc(false)],
+                    then_body: vec![Command::AndOr {
+                        first: Box::new(heredoc(true)),
+                        rest: vec![(AndOrOp::Or, echo("failed"))],
+                    }],
+                    else_body: None,
+                }],
+            },
+            Command::assign("x", Word(vec![WordPart::CommandSubst(vec![heredoc(true)])])),
+        ];
+
+        assert_eq!(print(&program), [
+            "cat > f <<'EOF'",
+            "$x",
+            "EOF",
+            "f() {",
+            "    if cat <<EOF; then",
+            "$x",
+            "EOF",
+            "        cat <<'EOF' || echo failed",
+            "$x",
+            "EOF",
+            "    fi",
+            "}",
+            "x=\"$(cat <<'EOF'",
+            "$x",
+            "EOF",
+            ")\"",
+            "",
+        ].join("\n"));
+    }
+}
@@ -0,0 +1,836 @@
+//! A recognizer for the POSIX shell command language, used to check `shell-literal` text at
+//! compile time. It only decides whether the text parses; it does not build a tree.
+
+use crate::target::Feature;
+
+/// A syntax error, with the character offset it was found at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShSyntaxError {
+    pub message: String,
+    pub offset: usize,
+}
+
+/// A construct that parses but is not POSIX, with the character offset it starts at.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bashism {
+    pub message: String,
+    pub feature: Feature,
+    pub offset: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Operator(&'static str),
+    Newline,
+    Eof,
+}
+
+const OPERATORS: [&str; 18] = [
+    "&&", "||", ";;", "<<-", "<<", ">>", "<&", ">&", "<>", ">|",
+    ";", "&", "|", "(", ")", "<", ">", "\n",
+];
+
+/// Operators only bash (and friends) understand, with the POSIX operator they are parsed as.
+const BASH_OPERATORS: [(&str, &str, &str, Feature); 6] = [
+    ("<<<", "<", "here-strings (`<<<`)", Feature::HereStrings),
+    ("&>>", ">>", "`&>>` redirections", Feature::AmpersandRedirections),
+    ("&>", ">", "`&>` redirections", Feature::AmpersandRedirections),
+    ("|&", "|", "`|&` pipes", Feature::AmpersandPipes),
+    (";;&", ";;", "`;;&` case terminators", Feature::CaseFallthrough),
+    (";&", ";;", "`;&` case terminators", Feature::CaseFallthrough),
+];
+
+const RESERVED: [&str; 16] = [
+    "if", "then", "else", "elif", "fi", "do", "done", "case", "esac",
+    "while", "until", "for", "in", "{", "}", "!",
+];
+
+const BASH_BUILTINS: [&str; 9] = [
+    "source", "local", "declare", "typeset", "let", "shopt", "pushd", "popd", "select",
+];
+
+/// Parses `text` as a POSIX sh program, returning the bashisms it uses.
+pub fn check(text: &str) -> Result<Vec<Bashism>, ShSyntaxError> {
+    let mut parser = Parser::new(text);
+    parser.parse_program()?;
+    Ok(parser.bashisms)
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    peeked: Option<(Token, usize)>,
+    heredocs: Vec<(String, bool)>,
+    bashisms: Vec<Bashism>,
+}
+
+impl Parser {
+    fn new(text: &str) -> Parser {
+        Parser {
+            chars: text.chars().collect(),
+            pos: 0,
+            peeked: None,
+            heredocs: vec![],
+            bashisms: vec![],
+        }
+    }
+
+    fn error<T>(&self, message: String, offset: usize) -> Result<T, ShSyntaxError> {
+        Err(ShSyntaxError { message, offset })
+    }
+
+    fn bashism(&mut self, feature: Feature, message: &str, offset: usize) {
+        self.bashisms.push(Bashism {
+            message: format!("{} are not POSIX", message),
+            feature,
+            offset,
+        });
+    }
+
+    fn char_at(&self, pos: usize) -> Option<char> {
+        self.chars.get(pos).copied()
+    }
+
+    fn starts_with(&self, s: &str) -> bool {
+        s.chars().enumerate().all(|(i, c)| self.char_at(self.pos + i) == Some(c))
+    }
+
+    // Tokens
+
+    fn peek(&mut self) -> Result<&Token, ShSyntaxError> {
+        if self.peeked.is_none() {
+            let token = self.scan_token()?;
+            self.peeked = Some(token);
+        }
+        Ok(&self.peeked.as_ref().unwrap().0)
+    }
+
+    fn peek_offset(&mut self) -> Result<usize, ShSyntaxError> {
+        self.peek()?;
+        Ok(self.peeked.as_ref().unwrap().1)
+    }
+
+    fn next(&mut self) -> Result<(Token, usize), ShSyntaxError> {
+        self.peek()?;
+        Ok(self.peeked.take().unwrap())
+    }
+
+    fn peek_is_word(&mut self, word: &str) -> Result<bool, ShSyntaxError> {
+        Ok(matches!(self.peek()?, Token::Word(w) if w == word))
+    }
+
+    fn peek_is_operator(&mut self, operator: &str) -> Result<bool, ShSyntaxError> {
+        Ok(matches!(self.peek()?, Token::Operator(o) if *o == operator))
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ShSyntaxError> {
+        let (token, offset) = self.next()?;
+        match token {
+            Token::Word(w) if w == word => Ok(()),
+            other => self.error(format!("expected `{}` but found {}", word, describe(&other)), offset),
+        }
+    }
+
+    fn expect_operator(&mut self, operator: &str) -> Result<(), ShSyntaxError> {
+        let (token, offset) = self.next()?;
+        match token {
+            Token::Operator(o) if o == operator => Ok(()),
+            other => self.error(format!("expected `{}` but found {}", operator, describe(&other)), offset),
+        }
+    }
+
+    fn skip_newlines(&mut self) -> Result<(), ShSyntaxError> {
+        while self.peek()? == &Token::Newline {
+            self.next()?;
+        }
+        Ok(())
+    }
+
+    fn scan_token(&mut self) -> Result<(Token, usize), ShSyntaxError> {
+        loop {
+            match self.char_at(self.pos) {
+                Some(' ') | Some('\t') => self.pos += 1,
+                Some('\\') if self.char_at(self.pos + 1) == Some('\n') => self.pos += 2,
+                Some('#') => {
+                    while !matches!(self.char_at(self.pos), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+
+        let start = self.pos;
+        if self.char_at(self.pos).is_none() {
+            return Ok((Token::Eof, start));
+        }
+
+        for (bash, posix, message, feature) in BASH_OPERATORS {
+            if self.starts_with(bash) {
+                self.bashism(feature, message, start);
+                self.pos += bash.chars().count();
+                return Ok((Token::Operator(posix), start));
+            }
+        }
+
+        if self.starts_with("<(") || self.starts_with(">(") {
+            self.bashism(Feature::ProcessSubstitutions, "`<(...)` and `>(...)` process substitutions", start);
+            self.pos += 2;
+            self.parse_compound_list()?;
+            return match self.next()? {
+                (Token::Operator(")"), _) => Ok((Token::Word(self.chars[start..self.pos].iter().collect()), start)),
+                (Token::Eof, _) => self.error(format!("unterminated `{}(`", self.chars[start]), start),
+                (other, offset) => self.error(unexpected(&other), offset),
+            };
+        }
+
+        for operator in OPERATORS {
+            if self.starts_with(operator) {
+                self.pos += operator.chars().count();
+                if operator == "\n" {
+                    self.read_heredocs()?;
+                    return Ok((Token::Newline, start));
+                }
+                return Ok((Token::Operator(operator), start));
+            }
+        }
+
+        let word = self.scan_word()?;
+        Ok((Token::Word(word), start))
+    }
+
+    fn scan_word(&mut self) -> Result<String, ShSyntaxError> {
+        let start = self.pos;
+        while let Some(c) = self.char_at(self.pos) {
+            match c {
+                ' ' | '\t' | '\n' | ';' | '&' | '|' | '<' | '>' | ')' => break,
+                '(' => {
+                    let word = self.chars[start..self.pos].iter().collect::<String>();
+                    if word.ends_with('=') && is_name(&word[..word.len() - 1]) {
+                        self.bashism(Feature::Arrays, "arrays", start);
+                        self.scan_balanced('(', ')')?;
+                    } else {
+                        break;
+                    }
+                }
+                '\\' => self.pos += 2,
+                '\'' => self.scan_single_quote()?,
+                '"' => self.scan_double_quote()?,
+                '`' => self.scan_backquote()?,
+                '$' => self.scan_dollar()?,
+                _ => self.pos += 1,
+            }
+        }
+
+        let word = self.chars[start..self.pos.min(self.chars.len())].iter().collect::<String>();
+        if has_brace_expansion(&word) {
+            self.bashism(Feature::BraceExpansions, "brace expansions", start);
+        }
+        Ok(word)
+    }
+
+    fn scan_single_quote(&mut self) -> Result<(), ShSyntaxError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.char_at(self.pos) {
+                Some('\'') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some(_) => self.pos += 1,
+                None => return self.error("unterminated single quote".to_string(), start),
+            }
+        }
+    }
+
+    fn scan_double_quote(&mut self) -> Result<(), ShSyntaxError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.char_at(self.pos) {
+                Some('"') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some('\\') => self.pos += 2,
+                Some('`') => self.scan_backquote()?,
+                Some('$') => self.scan_dollar()?,
+                Some(_) => self.pos += 1,
+                None => return self.error("unterminated double quote".to_string(), start),
+            }
+        }
+    }
+
+    fn scan_backquote(&mut self) -> Result<(), ShSyntaxError> {
+        let start = self.pos;
+        self.pos += 1;
+        loop {
+            match self.char_at(self.pos) {
+                Some('`') => {
+                    self.pos += 1;
+                    return Ok(());
+                }
+                Some('\\') => self.pos += 2,
+                Some(_) => self.pos += 1,
+                None => return self.error("unterminated backquote".to_string(), start),
+            }
+        }
+    }
+
+    /// Scans from an opening `open` to its matching `close`, skipping over quoted text.
+    fn scan_balanced(&mut self, open: char, close: char) -> Result<(), ShSyntaxError> {
+        let start = self.pos;
+        let mut depth = 0;
+        loop {
+            match self.char_at(self.pos) {
+                Some(c) if c == open => {
+                    depth += 1;
+                    self.pos += 1;
+                }
+                Some(c) if c == close => {
+                    depth -= 1;
+                    self.pos += 1;
+                    if depth == 0 {
+                        return Ok(());
+                    }
+                }
+                Some('\\') => self.pos += 2,
+                Some('\'') => self.scan_single_quote()?,
+                Some('"') => self.scan_double_quote()?,
+                Some('`') => self.scan_backquote()?,
+                Some('$') if self.char_at(self.pos + 1) != Some(open) => self.scan_dollar()?,
+                Some(_) => self.pos += 1,
+                None => return self.error(format!("unmatched `{}`", open), start),
+            }
+        }
+    }
+
+    fn scan_dollar(&mut self) -> Result<(), ShSyntaxError> {
+        let start = self.pos;
+        match self.char_at(self.pos + 1) {
+            Some('(') if self.char_at(self.pos + 2) == Some('(') => {
+                self.pos += 1;
+                self.scan_balanced('(', ')')
+            }
+            Some('(') => {
+                self.pos += 2;
+                self.parse_compound_list()?;
+                match self.next()? {
+                    (Token::Operator(")"), _) => Ok(()),
+                    (Token::Eof, _) => self.error("unterminated `$(`".to_string(), start),
+                    (other, offset) => self.error(unexpected(&other), offset),
+                }
+            }
+            Some('{') => {
+                self.pos += 1;
+                self.scan_balanced('{', '}')?;
+                let inner = self.chars[start + 2..self.pos - 1].iter().collect::<String>();
+                self.check_parameter_expansion(&inner, start);
+                Ok(())
+            }
+            Some('\'') => {
+                self.bashism(Feature::DollarSingleQuotes, "`$'...'` strings", start);
+                self.pos += 1;
+                loop {
+                    self.pos += 1;
+                    match self.char_at(self.pos) {
+                        Some('\\') => self.pos += 1,
+                        Some('\'') => break,
+                        Some(_) => {}
+                        None => return self.error("unterminated `$'`".to_string(), start),
+                    }
+                }
+                self.pos += 1;
+                Ok(())
+            }
+            _ => {
+                self.pos += 1;
+                Ok(())
+            }
+        }
+    }
+
+    fn check_parameter_expansion(&mut self, inner: &str, offset: usize) {
+        let name = inner.trim_start_matches('#');
+        if inner.starts_with('!') {
+            self.bashism(Feature::IndirectExpansions, "`${!name}` indirect expansions", offset);
+            return;
+        }
+
+        let name_len = name
+            .char_indices()
+            .find(|(i, c)| !(c.is_ascii_alphanumeric() || *c == '_' || (*i == 0 && "@*?$!-#".contains(*c))))
+            .map(|(i, _)| i)
+            .unwrap_or(name.len());
+        let rest = &name[name_len..];
+
+        if rest.starts_with('[') {
+            self.bashism(Feature::Arrays, "arrays", offset);
+        } else if rest.starts_with('/') {
+            self.bashism(Feature::PatternSubstitutions, "`${var/pattern/replacement}` substitutions", offset);
+        } else if rest.starts_with('^') || rest.starts_with(',') {
+            self.bashism(Feature::CaseModifications, "`${var^}` and `${var,}` case modifications", offset);
+        } else if rest.starts_with(':') && !matches!(rest.chars().nth(1), Some('-' | '=' | '?' | '+')) {
+            self.bashism(Feature::Substrings, "`${var:offset:length}` substrings", offset);
+        }
+    }
+
+    fn read_heredocs(&mut self) -> Result<(), ShSyntaxError> {
+        for (delimiter, strip_tabs) in std::mem::take(&mut self.heredocs) {
+            let start = self.pos;
+            loop {
+                if self.pos >= self.chars.len() {
+                    return self.error(format!("here-document is missing its `{}` delimiter", delimiter), start);
+                }
+                let end = self.chars[self.pos..]
+                    .iter()
+                    .position(|c| *c == '\n')
+                    .map(|i| self.pos + i)
+                    .unwrap_or(self.chars.len());
+                let line = self.chars[self.pos..end].iter().collect::<String>();
+                self.pos = (end + 1).min(self.chars.len());
+
+                let line = if strip_tabs { line.trim_start_matches('\t') } else { &line };
+                if line == delimiter {
+                    break;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Grammar
+
+    fn parse_program(&mut self) -> Result<(), ShSyntaxError> {
+        self.parse_compound_list()?;
+        let (token, offset) = self.next()?;
+        match token {
+            Token::Eof if self.heredocs.is_empty() => Ok(()),
+            Token::Eof => self.error("here-document is missing its delimiter".to_string(), offset),
+            Token::Word(w) if closes(&w).is_some() => self.error(
+                format!("`{}` without a matching `{}`", w, closes(&w).unwrap()),
+                offset,
+            ),
+            other => self.error(unexpected(&other), offset),
+        }
+    }
+
+    /// Parses commands until a token that can't start one; returns how many were parsed.
+    fn parse_compound_list(&mut self) -> Result<usize, ShSyntaxError> {
+        let mut count = 0;
+        loop {
+            self.skip_newlines()?;
+            match self.peek()? {
+                Token::Eof => return Ok(count),
+                Token::Operator(")") => return Ok(count),
+                Token::Operator(";;") => return Ok(count),
+                Token::Word(w) if closes(w).is_some() => return Ok(count),
+                Token::Word(w) if w == "then" || w == "do" || w == "in" => return Ok(count),
+                _ => {}
+            }
+
+            self.parse_and_or()?;
+            count += 1;
+            match self.peek()? {
+                Token::Operator(";") | Token::Operator("&") | Token::Newline => {
+                    self.next()?;
+                }
+                _ => return Ok(count),
+            }
+        }
+    }
+
+    fn parse_body(&mut self, keyword: &str) -> Result<(), ShSyntaxError> {
+        if self.parse_compound_list()? == 0 {
+            let (token, offset) = self.next()?;
+            return self.error(format!("empty `{}` body before {}", keyword, describe(&token)), offset);
+        }
+        Ok(())
+    }
+
+    fn parse_and_or(&mut self) -> Result<(), ShSyntaxError> {
+        self.parse_pipeline()?;
+        while self.peek_is_operator("&&")? || self.peek_is_operator("||")? {
+            self.next()?;
+            self.skip_newlines()?;
+            self.parse_pipeline()?;
+        }
+        Ok(())
+    }
+
+    fn parse_pipeline(&mut self) -> Result<(), ShSyntaxError> {
+        if self.peek_is_word("!")? {
+            self.next()?;
+        }
+        self.parse_command()?;
+        while self.peek_is_operator("|")? {
+            self.next()?;
+            self.skip_newlines()?;
+            self.parse_command()?;
+        }
+        Ok(())
+    }
+
+    fn parse_command(&mut self) -> Result<(), ShSyntaxError> {
+        let offset = self.peek_offset()?;
+        let token = self.peek()?.clone();
+        match &token {
+            Token::Word(w) => match w.as_str() {
+                "if" => self.parse_if()?,
+                "while" | "until" => {
+                    self.next()?;
+                    self.parse_body(w)?;
+                    self.parse_do_group()?;
+                }
+                "for" => self.parse_for()?,
+                "case" => self.parse_case()?,
+                "{" => {
+                    self.next()?;
+                    self.parse_body("{")?;
+                    self.expect_word("}")?;
+                }
+                "function" => {
+                    self.bashism(Feature::FunctionKeyword, "`function` definitions", offset);
+                    self.next()?;
+                    self.next()?;
+                    if self.peek_is_operator("(")? {
+                        self.next()?;
+                        self.expect_operator(")")?;
+                    }
+                    self.skip_newlines()?;
+                    return self.parse_command();
+                }
+                "[[" => {
+                    self.bashism(Feature::DoubleBracketTests, "`[[ ]]` tests", offset);
+                    return self.parse_simple_command();
+                }
+                _ if closes(w).is_some() => {
+                    return self.error(format!("`{}` without a matching `{}`", w, closes(w).unwrap()), offset);
+                }
+                _ if RESERVED.contains(&w.as_str()) => return self.error(format!("unexpected `{}`", w), offset),
+                _ => return self.parse_simple_command(),
+            },
+            Token::Operator("(") => {
+                self.next()?;
+                if self.char_at(self.pos) == Some('(') {
+                    self.bashism(Feature::ArithmeticCommands, "`((...))` arithmetic commands", offset);
+                    self.pos -= 1;
+                    self.scan_balanced('(', ')')?;
+                } else {
+                    self.parse_body("(")?;
+                    self.expect_operator(")")?;
+                }
+            }
+            Token::Operator(o) if is_redirection(o) => return self.parse_simple_command(),
+            other => return self.error(unexpected(other), offset),
+        }
+        self.parse_redirections()
+    }
+
+    fn parse_if(&mut self) -> Result<(), ShSyntaxError> {
+        self.expect_word("if")?;
+        self.parse_body("if")?;
+        self.expect_word("then")?;
+        self.parse_body("then")?;
+        loop {
+            if self.peek_is_word("elif")? {
+                self.next()?;
+                self.parse_body("elif")?;
+                self.expect_word("then")?;
+                self.parse_body("then")?;
+            } else if self.peek_is_word("else")? {
+                self.next()?;
+                self.parse_body("else")?;
+                return self.expect_word("fi");
+            } else {
+                return self.expect_word("fi");
+            }
+        }
+    }
+
+    fn parse_do_group(&mut self) -> Result<(), ShSyntaxError> {
+        self.skip_newlines()?;
+        self.expect_word("do")?;
+        self.parse_body("do")?;
+        self.expect_word("done")
+    }
+
+    fn parse_for(&mut self) -> Result<(), ShSyntaxError> {
+        self.expect_word("for")?;
+        let (token, offset) = self.next()?;
+        match token {
+            Token::Word(name) if is_name(&name) => {}
+            Token::Operator("(") => {
+                self.bashism(Feature::ArithmeticCommands, "`for ((...))` loops", offset);
+                self.pos -= 1;
+                self.scan_balanced('(', ')')?;
+                if self.peek_is_operator(";")? {
+                    self.next()?;
+                }
+                return self.parse_do_group();
+            }
+            other => {
+                let message = format!("expected a variable name after `for` but found {}", describe(&other));
+                return self.error(message, offset);
+            }
+        }
+
+        self.skip_newlines()?;
+        if self.peek_is_word("in")? {
+            self.next()?;
+            while let Token::Word(_) = self.peek()? {
+                self.next()?;
+            }
+        }
+        if self.peek_is_operator(";")? {
+            self.next()?;
+        }
+        self.parse_do_group()
+    }
+
+    fn parse_case(&mut self) -> Result<(), ShSyntaxError> {
+        self.expect_word("case")?;
+        let (token, offset) = self.next()?;
+        if !matches!(token, Token::Word(_)) {
+            return self.error(format!("expected a word after `case` but found {}", describe(&token)), offset);
+        }
+        self.skip_newlines()?;
+        self.expect_word("in")?;
+        self.skip_newlines()?;
+
+        while !self.peek_is_word("esac")? {
+            if self.peek_is_operator("(")? {
+                self.next()?;
+            }
+            loop {
+                let (token, offset) = self.next()?;
+                if !matches!(token, Token::Word(_)) {
+                    return self.error(format!("expected a case pattern but found {}", describe(&token)), offset);
+                }
+                if !self.peek_is_operator("|")? {
+                    break;
+                }
+                self.next()?;
+            }
+            self.expect_operator(")")?;
+            self.parse_compound_list()?;
+            if self.peek_is_operator(";;")? {
+                self.next()?;
+                self.skip_newlines()?;
+            } else if !self.peek_is_word("esac")? {
+                let (token, offset) = self.next()?;
+                return self.error(format!("expected `;;` or `esac` but found {}", describe(&token)), offset);
+            }
+        }
+        self.expect_word("esac")
+    }
+
+    fn parse_redirections(&mut self) -> Result<(), ShSyntaxError> {
+        while let Token::Operator(o) = self.peek()? {
+            if !is_redirection(o) {
+                break;
+            }
+            self.parse_redirection()?;
+        }
+        Ok(())
+    }
+
+    fn parse_redirection(&mut self) -> Result<(), ShSyntaxError> {
+        let (operator, _) = self.next()?;
+        let (target, offset) = self.next()?;
+        match (operator, target) {
+            (Token::Operator(o), Token::Word(w)) if o == "<<" || o == "<<-" => {
+                let delimiter = w.chars().filter(|c| !"'\"\\".contains(*c)).collect();
+                self.heredocs.push((delimiter, o == "<<-"));
+                Ok(())
+            }
+            (_, Token::Word(_)) => Ok(()),
+            (Token::Operator(o), other) => self.error(
+                format!("expected a word after `{}` but found {}", o, describe(&other)),
+                offset,
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    fn parse_simple_command(&mut self) -> Result<(), ShSyntaxError> {
+        let mut words = vec![];
+        loop {
+            let offset = self.peek_offset()?;
+            match self.peek()?.clone() {
+                Token::Word(w) => {
+                    self.next()?;
+                    if words.is_empty() {
+                        if let Some(builtin) = BASH_BUILTINS.iter().find(|b| **b == w) {
+                            let feature = match *builtin {
+                                "local" => Feature::Local,
+                                _ => Feature::Builtin(builtin),
+                            };
+                            self.bashism(feature, &format!("`{}` commands", w), offset);
+                        }
+                    } else if words.len() == 1 && words[0] == "echo" && w.starts_with("-e") {
+                        self.bashism(Feature::EchoEscapes, "`echo -e` options", offset);
+                    } else if w == "==" && (words[0] == "[" || words[0] == "test") {
+                        self.bashism(Feature::DoubleEqualsTests, "`==` comparisons", offset);
+                    }
+                    words.push(w);
+                }
+                Token::Operator("(") if words.len() == 1 && is_name(&words[0]) => {
+                    self.next()?;
+                    self.expect_operator(")")?;
+                    self.skip_newlines()?;
+                    return self.parse_command();
+                }
+                Token::Operator(o) if is_redirection(o) => self.parse_redirection()?,
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The reserved word that opens the block `word` closes.
+fn closes(word: &str) -> Option<&'static str> {
+    match word {
+        "fi" => Some("if"),
+        "elif" | "else" => Some("if"),
+        "done" => Some("do"),
+        "esac" => Some("case"),
+        "}" => Some("{"),
+        _ => None,
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Word(w) => format!("`{}`", w),
+        Token::Operator(o) => format!("`{}`", o),
+        Token::Newline => "a newline".to_string(),
+        Token::Eof => "the end of the text".to_string(),
+    }
+}
+
+fn unexpected(token: &Token) -> String {
+    match token {
+        Token::Eof => "unexpected end of the text".to_string(),
+        other => format!("unexpected {}", describe(other)),
+    }
+}
+
+fn is_redirection(operator: &str) -> bool {
+    matches!(operator, "<" | ">" | ">>" | "<&" | ">&" | "<>" | ">|" | "<<" | "<<-")
+}
+
+fn is_name(word: &str) -> bool {
+    let mut chars = word.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Whether `word` has an unquoted `{...,...}` or `{a..b}`, which bash expands and sh doesn't.
+fn has_brace_expansion(word: &str) -> bool {
+    if word.contains('\'') || word.contains('"') || word.contains('$') {
+        return false;
+    }
+    match (word.find('{'), word.rfind('}')) {
+        (Some(open), Some(close)) if open < close => {
+            let inner = &word[open + 1..close];
+            inner.contains(',') || inner.contains("..")
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bashisms(text: &str) -> Vec<String> {
+        check(text).unwrap().into_iter().map(|b| b.message).collect()
+    }
+
+    #[test]
+    fn valid_programs() {
+        let programs = [
+            "echo hello world",
+            "ls | grep x > out 2>&1 && echo ok || echo no &",
+            "if test -f x; then cat x; elif true; then :; else echo none; fi",
+            "for f in *.txt; do\n  echo \"$f\"\ndone",
+            "for arg do shift; done",
+            "while read -r line; do echo \"$line\"; done < file",
+            "case $x in\n  a|b) echo ab ;;\n  (*.tar.gz) echo tar ;;\n  *) echo other\nesac",
+            "f() { echo \"$(basename \"$1\")\" `date`; }",
+            "( cd /tmp && ls ) > /dev/null",
+            "echo ${x:-default} ${#x} ${x%.txt} $((1 + (2 * 3)))",
+            "cat <<EOF\nhello $name\nEOF\necho after",
+            "cat <<-'END'\n\tliteral\n\tEND",
+            "echo 'it''s' \"a \\\"quoted\\\" word\" # comment with ) and '",
+            "! grep -q x file",
+        ];
+
+        for program in programs {
+            assert_eq!(check(program), Ok(vec![]), "{}", program);
+        }
+    }
+
+    #[test]
+    fn syntax_errors() {
+        let cases = [
+            ("echo 'unterminated", "unterminated single quote", 5),
+            ("echo \"unterminated", "unterminated double quote", 5),
+            ("echo hi; fi", "`fi` without a matching `if`", 9),
+            ("if true; then echo", "expected `fi` but found the end of the text", 18),
+            ("while true; do done", "empty `do` body before `done`", 15),
+            ("echo $(ls", "unterminated `$(`", 5),
+            ("cat <(ls", "unterminated `<(`", 4),
+            ("echo )", "unexpected `)`", 5),
+            ("echo hi &&", "unexpected end of the text", 10),
+            ("cat <<EOF\nno end", "here-document is missing its `EOF` delimiter", 10),
+            ("ls >", "expected a word after `>` but found the end of the text", 4),
+            ("case x in a) echo; esac done", "`done` without a matching `do`", 24),
+        ];
+
+        for (program, message, offset) in cases {
+            assert_eq!(
+                check(program),
+                Err(ShSyntaxError { message: message.to_string(), offset }),
+                "{}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn detects_bashisms() {
+        let cases = [
+            ("[[ -f x ]] && echo y", "`[[ ]]` tests"),
+            ("function f { echo; }", "`function` definitions"),
+            ("echo $'a\\tb'", "`$'...'` strings"),
+            ("echo ${x:1:2}", "`${var:offset:length}` substrings"),
+            ("echo ${x//a/b}", "`${var/pattern/replacement}` substitutions"),
+            ("echo ${!ref}", "`${!name}` indirect expansions"),
+            ("arr=(a b c)", "arrays"),
+            ("cat <<< \"$x\"", "here-strings (`<<<`)"),
+            ("ls &> /dev/null", "`&>` redirections"),
+            ("source ./lib.sh", "`source` commands"),
+            ("echo {a,b}.txt", "brace expansions"),
+            ("[ \"$a\" == b ]", "`==` comparisons"),
+            ("echo -e 'a\\n'", "`echo -e` options"),
+            ("diff <(sort a) b", "`<(...)` and `>(...)` process substitutions"),
+        ];
+
+        for (program, message) in cases {
+            assert_eq!(bashisms(program), vec![format!("{} are not POSIX", message)], "{}", program);
+        }
+    }
+
+    #[test]
+    fn bashism_features() {
+        let features = |text| check(text).unwrap().into_iter().map(|b| b.feature).collect::<Vec<Feature>>();
+        assert_eq!(features("local x; declare -a y"), vec![Feature::Local, Feature::Builtin("declare")]);
+        assert_eq!(features("ls &>> log |& cat"), vec![Feature::AmpersandRedirections, Feature::AmpersandPipes]);
+        assert_eq!(features("ls | tee >(wc -l) > log"), vec![Feature::ProcessSubstitutions]);
+    }
+}
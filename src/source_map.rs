@@ -0,0 +1,137 @@
+//! Maps the lines of a generated script back to the `.shisp` forms they were generated from.
+//!
+//! The map is appended to the script as a table of comments, so `shisp trace` only needs the
+//! script itself to turn the line numbers in `sh -x` output and shell error messages back into
+//! Shisp positions.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+const HEADER: &str = "# shisp source map: ";
+
+lazy_static! {
+    /// The script line number at the start of a trace line (with `PS4='+ ${LINENO}: '`) or of an
+    /// error message from dash, bash, busybox (`script: 12:`, `script: line 12:`) or zsh (`script:12:`).
+    static ref LINE: Regex = Regex::new(r"^(?:\++ ?(\d+): |\S+?: (?:line )?(\d+): |\S+?:(\d+): )").unwrap();
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    /// The row and column of the form each output line came from, starting with line 1.
+    lines: Vec<Option<(usize, usize)>>,
+}
+
+impl SourceMap {
+    pub fn new(lines: Vec<Option<(usize, usize)>>) -> SourceMap {
+        SourceMap { lines }
+    }
+
+    /// The row and column of the form that produced the 1-based script `line`.
+    pub fn lookup(&self, line: usize) -> Option<(usize, usize)> {
+        self.lines.get(line.checked_sub(1)?).copied().flatten()
+    }
+
+    /// Renders the map as comments for the end of the script, one per run of lines coming from
+    /// the same form: `# first-last row:col`, all counted from 1.
+    pub fn to_comments(&self, source: &str) -> String {
+        let mut out = format!("{}{}\n", HEADER, source);
+        let mut start = 0;
+        while start < self.lines.len() {
+            let end = (start..self.lines.len())
+                .find(|i| self.lines[*i] != self.lines[start])
+                .unwrap_or(self.lines.len());
+            if let Some((row, col)) = self.lines[start] {
+                if end - start == 1 {
+                    out.push_str(&format!("# {} {}:{}\n", start + 1, row + 1, col + 1));
+                } else {
+                    out.push_str(&format!("# {}-{} {}:{}\n", start + 1, end, row + 1, col + 1));
+                }
+            }
+            start = end;
+        }
+        out
+    }
+
+    /// Reads back the map a script ends with, along with the name of the source it refers to.
+    pub fn from_script(script: &str) -> Option<(String, SourceMap)> {
+        let mut lines = script.lines().skip_while(|l| !l.starts_with(HEADER));
+        let source = lines.next()?[HEADER.len()..].to_string();
+
+        let mut map = vec![];
+        for (first, last, position) in lines.map_while(parse_entry) {
+            if map.len() < last {
+                map.resize(last, None);
+            }
+            for entry in &mut map[first - 1..last] {
+                *entry = Some(position);
+            }
+        }
+        Some((source, SourceMap::new(map)))
+    }
+
+    /// Prefixes a line of `sh -x` output or of a shell error message with the Shisp position of the
+    /// script line it mentions. Traces need `PS4` to hold the line number, as in
+    /// `PS4='+ ${LINENO}: '`; error messages carry it already. Other lines are returned unchanged.
+    pub fn trace(&self, source: &str, line: &str) -> String {
+        let number = LINE
+            .captures(line)
+            .and_then(|c| c.iter().skip(1).flatten().next())
+            .and_then(|m| m.as_str().parse::<usize>().ok());
+        match number.and_then(|n| self.lookup(n)) {
+            Some((row, col)) => format!("{}:{}:{}: {}", source, row + 1, col + 1, line),
+            None => line.to_string(),
+        }
+    }
+}
+
+/// Reads a `# first-last row:col` comment as the 1-based line range and the 0-based position.
+fn parse_entry(line: &str) -> Option<(usize, usize, (usize, usize))> {
+    let (range, position) = line.strip_prefix("# ")?.split_once(' ')?;
+    let (first, last) = range.split_once('-').unwrap_or((range, range));
+    let (row, col) = position.split_once(':')?;
+    let number = |s: &str| s.parse::<usize>().ok().filter(|n| *n > 0);
+    let (first, last) = (number(first)?, number(last)?);
+    Some((first, last.max(first), (number(row)? - 1, number(col)? - 1)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> SourceMap {
+        SourceMap::new(vec![None, Some((0, 0)), Some((0, 0)), Some((2, 4)), None])
+    }
+
+    #[test]
+    fn comments_round_trip() {
+        let comments = map().to_comments("in.shisp");
+        assert_eq!(comments, "# shisp source map: in.shisp\n# 2-3 1:1\n# 4 3:5\n");
+
+        let script = format!("#!/bin/sh\necho hi\n{}# a note of the user's\n# 5 1:1\n", comments);
+        let (source, read) = SourceMap::from_script(&script).unwrap();
+        assert_eq!(source, "in.shisp");
+        assert_eq!(read.lookup(1), None);
+        assert_eq!(read.lookup(3), Some((0, 0)));
+        assert_eq!(read.lookup(4), Some((2, 4)));
+        assert_eq!(read.lookup(5), None);
+        assert_eq!(SourceMap::from_script("#!/bin/sh\n"), None);
+    }
+
+    #[test]
+    fn test_trace() {
+        let map = map();
+        assert_eq!(map.trace("in.shisp", "+ 4: s_f a"), "in.shisp:3:5: + 4: s_f a");
+        assert_eq!(map.trace("in.shisp", "++ 2: echo"), "in.shisp:1:1: ++ 2: echo");
+        assert_eq!(map.trace("in.shisp", "out.sh: 4: foo: not found"), "in.shisp:3:5: out.sh: 4: foo: not found");
+        assert_eq!(
+            map.trace("in.shisp", "out.sh: line 3: foo: command not found"),
+            "in.shisp:1:1: out.sh: line 3: foo: command not found"
+        );
+        assert_eq!(
+            map.trace("in.shisp", "out.sh:2: command not found: foo"),
+            "in.shisp:1:1: out.sh:2: command not found: foo"
+        );
+        assert_eq!(map.trace("in.shisp", "+ 5: echo"), "+ 5: echo");
+        assert_eq!(map.trace("in.shisp", "plain output"), "plain output");
+    }
+}
@@ -0,0 +1,186 @@
+//! The shells the compiler can generate code for, and the features beyond POSIX sh each of them
+//! understands. A program that needs no such feature runs on all of them.
+
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Target {
+    #[default]
+    Posix,
+    Dash,
+    Bash,
+    BusyboxAsh,
+    Zsh,
+}
+
+/// A construct that is not part of POSIX sh, used by the code generator or by a `shell-literal`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Feature {
+    Local,
+    DoubleBracketTests,
+    DollarSingleQuotes,
+    Arrays,
+    HereStrings,
+    AmpersandRedirections,
+    AmpersandPipes,
+    CaseFallthrough,
+    FunctionKeyword,
+    ArithmeticCommands,
+    IndirectExpansions,
+    PatternSubstitutions,
+    CaseModifications,
+    Substrings,
+    BraceExpansions,
+    EchoEscapes,
+    DoubleEqualsTests,
+    AssociativeArrays,
+    ProcessSubstitutions,
+    Builtin(&'static str),
+}
+
+impl Target {
+    pub const ALL: [Target; 5] = [Target::Posix, Target::Dash, Target::Bash, Target::BusyboxAsh, Target::Zsh];
+
+    /// Reads a target as given to `--target`.
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "posix" | "sh" => Some(Target::Posix),
+            "dash" => Some(Target::Dash),
+            "bash" => Some(Target::Bash),
+            "busybox" | "ash" => Some(Target::BusyboxAsh),
+            "zsh" => Some(Target::Zsh),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Target::Posix => "posix",
+            Target::Dash => "dash",
+            Target::Bash => "bash",
+            Target::BusyboxAsh => "busybox",
+            Target::Zsh => "zsh",
+        }
+    }
+
+    pub fn shebang(&self) -> &'static str {
+        match self {
+            Target::Posix => "#!/bin/sh",
+            Target::Dash => "#!/bin/dash",
+            Target::Bash => "#!/usr/bin/env bash",
+            Target::BusyboxAsh => "#!/bin/ash",
+            Target::Zsh => "#!/usr/bin/env zsh",
+        }
+    }
+
+    pub fn supports(&self, feature: Feature) -> bool {
+        use Feature::*;
+        use Target::*;
+
+        match (self, feature) {
+            (Posix, _) => false,
+            (_, Local) => true,
+            (Dash, _) => false,
+            (Bash, _) => true,
+            (BusyboxAsh, DoubleBracketTests | DollarSingleQuotes | HereStrings | AmpersandRedirections
+                | FunctionKeyword | PatternSubstitutions | Substrings | EchoEscapes | DoubleEqualsTests) => true,
+            (BusyboxAsh, Builtin(name)) => matches!(name, "source" | "let"),
+            (BusyboxAsh, _) => false,
+            // zsh has associative arrays, but lists their keys with `${(k)name}` rather than `${!name[@]}`.
+            (Zsh, IndirectExpansions | CaseModifications | DoubleEqualsTests | AssociativeArrays) => false,
+            (Zsh, Builtin(name)) => name != "shopt",
+            (Zsh, _) => true,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl fmt::Display for Feature {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Feature::Local => write!(f, "`local` variables"),
+            Feature::DoubleBracketTests => write!(f, "`[[ ]]` tests"),
+            Feature::DollarSingleQuotes => write!(f, "`$'...'` strings"),
+            Feature::Arrays => write!(f, "arrays"),
+            Feature::HereStrings => write!(f, "here-strings (`<<<`)"),
+            Feature::AmpersandRedirections => write!(f, "`&>` redirections"),
+            Feature::AmpersandPipes => write!(f, "`|&` pipes"),
+            Feature::CaseFallthrough => write!(f, "`;&` and `;;&` case terminators"),
+            Feature::FunctionKeyword => write!(f, "`function` definitions"),
+            Feature::ArithmeticCommands => write!(f, "`((...))` arithmetic"),
+            Feature::IndirectExpansions => write!(f, "`${{!name}}` indirect expansions"),
+            Feature::PatternSubstitutions => write!(f, "`${{var/pattern/replacement}}` substitutions"),
+            Feature::CaseModifications => write!(f, "`${{var^}}` and `${{var,}}` case modifications"),
+            Feature::Substrings => write!(f, "`${{var:offset:length}}` substrings"),
+            Feature::BraceExpansions => write!(f, "brace expansions"),
+            Feature::EchoEscapes => write!(f, "`echo -e` options"),
+            Feature::DoubleEqualsTests => write!(f, "`==` comparisons"),
+            Feature::AssociativeArrays => write!(f, "`declare -A` associative arrays"),
+            Feature::ProcessSubstitutions => write!(f, "`<(...)` and `>(...)` process substitutions"),
+            Feature::Builtin(name) => write!(f, "`{}` commands", name),
+        }
+    }
+}
+
+/// Describes which targets can run a program that uses `features`.
+pub fn report(features: &[Feature]) -> String {
+    if features.is_empty() {
+        return "no features beyond POSIX sh are required\n".to_string();
+    }
+
+    let mut out = String::from("features beyond POSIX sh required:\n");
+    for feature in features {
+        let targets = Target::ALL
+            .iter()
+            .filter(|t| t.supports(*feature))
+            .map(|t| t.name())
+            .collect::<Vec<&str>>();
+        let targets = if targets.is_empty() { "none".to_string() } else { targets.join(", ") };
+        out.push_str(&format!("  {} (supported by: {})\n", feature, targets));
+    }
+
+    let usable = Target::ALL
+        .iter()
+        .filter(|t| features.iter().all(|f| t.supports(*f)))
+        .map(|t| t.name())
+        .collect::<Vec<&str>>();
+    if usable.is_empty() {
+        out.push_str("no single target supports all of them\n");
+    } else {
+        out.push_str(&format!("usable targets: {}\n", usable.join(", ")));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn target_names() {
+        for target in Target::ALL {
+            assert_eq!(Target::from_name(target.name()), Some(target));
+        }
+        assert_eq!(Target::from_name("ash"), Some(Target::BusyboxAsh));
+        assert_eq!(Target::from_name("fish"), None);
+        assert_eq!(Target::default(), Target::Posix);
+    }
+
+    #[test]
+    fn test_report() {
+        assert_eq!(report(&[]), "no features beyond POSIX sh are required\n");
+        assert_eq!(report(&[Feature::Local, Feature::DoubleBracketTests]), [
+            "features beyond POSIX sh required:",
+            "  `local` variables (supported by: dash, bash, busybox, zsh)",
+            "  `[[ ]]` tests (supported by: bash, busybox, zsh)",
+            "usable targets: bash, busybox, zsh",
+            "",
+        ].join("\n"));
+        assert!(report(&[Feature::Arrays, Feature::CaseModifications]).ends_with("usable targets: bash\n"));
+    }
+}